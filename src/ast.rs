@@ -1,6 +1,14 @@
-use crate::autonum::AutoNum;
-use crate::operator::{BinaryOp, UnaryOp};
-use crate::units::Quantity;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::autonum::{AutoNum, MAX_DIGITS};
+use crate::complex::Complex;
+use crate::env::Environment;
+use crate::operator::{BinaryOp, FunctionOp, UnaryOp};
+use crate::units::{Quantity, TemperatureKind};
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI, TAU};
 
 pub struct EvalError {
     pub error: String,
@@ -22,27 +30,124 @@ pub struct Unary {
 pub struct WithUnits {
     pub inner: Expr,
     pub units: Quantity,
+    pub offset: Option<AutoNum>,
+}
+
+pub struct Builtin {
+    pub op: FunctionOp,
+    pub args: Vec<Expr>,
+}
+
+pub struct Interval {
+    pub lo: Expr,
+    pub hi: Expr,
+}
+
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Expr>,
+}
+
+pub struct Parameter {
+    pub name: String,
+    pub units: Option<(String, Quantity)>,
+}
+
+pub struct Function {
+    pub params: Vec<Parameter>,
+    pub body: Expr,
+}
+
+// A function together with the values of the variables its body used when it was defined
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub captured: HashMap<String, Quantity>,
+}
+
+impl Closure {
+    pub fn new(function: Rc<Function>, env: &Environment) -> Closure {
+        let mut names = HashSet::new();
+        function.body.collect_variables(&mut names);
+        let captured = names
+            .into_iter()
+            .filter(|name| function.params.iter().all(|p| &p.name != name))
+            .filter_map(|name| env.get_variable(&name).map(|x| (name, x.clone())))
+            .collect();
+        Closure { function, captured }
+    }
+}
+
+pub enum Statement {
+    Assign(String, Expr),
+    Define(String, Rc<Function>),
 }
 
 pub enum Expr {
     Quantity(Quantity),
+    Variable(String),
     Binary(Box<Binary>),
     Unary(Box<Unary>),
     WithUnits(Box<WithUnits>),
+    Builtin(Box<Builtin>),
+    Call(Box<Call>),
+    Interval(Box<Interval>),
 }
 
 impl Expr {
-    pub fn eval(&self) -> EvalResult {
+    fn collect_variables(&self, names: &mut HashSet<String>) {
+        match self {
+            Expr::Quantity(_) => {}
+            Expr::Variable(name) => {
+                names.insert(name.clone());
+            }
+            Expr::Binary(b) => {
+                b.lhs.collect_variables(names);
+                b.rhs.collect_variables(names);
+            }
+            Expr::Unary(u) => u.operand.collect_variables(names),
+            Expr::WithUnits(wu) => wu.inner.collect_variables(names),
+            Expr::Builtin(b) => b.args.iter().for_each(|arg| arg.collect_variables(names)),
+            Expr::Call(c) => c.args.iter().for_each(|arg| arg.collect_variables(names)),
+            Expr::Interval(i) => {
+                i.lo.collect_variables(names);
+                i.hi.collect_variables(names);
+            }
+        }
+    }
+
+    pub fn eval(&self, env: &Environment) -> EvalResult {
         match self {
             Expr::Quantity(x) => Ok(x.clone()),
-            Expr::Binary(b) => b.eval(),
-            Expr::Unary(u) => u.eval(),
+            Expr::Variable(name) => match env.get_variable(name) {
+                Some(x) => Ok(x.clone()),
+                None => Err(EvalError {
+                    error: format!("'{name}' is not defined"),
+                }),
+            },
+            Expr::Binary(b) => b.eval(env),
+            Expr::Unary(u) => u.eval(env),
+            Expr::Builtin(b) => b.eval(env),
+            Expr::Call(c) => c.eval(env),
+            Expr::Interval(i) => i.eval(env),
             Expr::WithUnits(wu) => {
-                let inner_result = wu.inner.eval()?;
-                Ok(Quantity::new(
+                let inner_result = wu.inner.eval(env)?;
+                let scaled = Quantity::new(
                     inner_result.value.auto_mul(&wu.units.value),
                     inner_result.units.combine(&wu.units.units, false)?,
-                ))
+                )
+                .with_uncertainty(
+                    inner_result
+                        .uncertainty
+                        .map(|u| u * wu.units.value.cast().abs()),
+                )?;
+                match &wu.offset {
+                    Some(offset) => Ok(Quantity {
+                        value: scaled.value.auto_add(offset),
+                        temperature: TemperatureKind::Absolute,
+                        ..scaled
+                    }),
+                    None => Ok(scaled),
+                }
             }
         }
     }
@@ -55,55 +160,647 @@ impl Expr {
         Expr::Unary(Box::new(Unary { op, operand }))
     }
 
+    // Signs are applied before offsetting, so that -40 degC is read as (-40) degC
+    pub fn prefixed(op: UnaryOp, operand: Expr) -> Expr {
+        match operand {
+            Expr::WithUnits(wu) if wu.offset.is_some() => {
+                let WithUnits {
+                    inner,
+                    units,
+                    offset,
+                } = *wu;
+                Expr::WithUnits(Box::new(WithUnits {
+                    inner: Expr::unary(op, inner),
+                    units,
+                    offset,
+                }))
+            }
+            _ => Expr::unary(op, operand),
+        }
+    }
+
     pub fn with_units(inner: Expr, units: Quantity) -> Expr {
-        Expr::WithUnits(Box::new(WithUnits { inner, units }))
+        Expr::WithUnits(Box::new(WithUnits {
+            inner,
+            units,
+            offset: None,
+        }))
+    }
+
+    pub fn with_affine_units(inner: Expr, units: Quantity, offset: AutoNum) -> Expr {
+        Expr::WithUnits(Box::new(WithUnits {
+            inner,
+            units,
+            offset: Some(offset),
+        }))
+    }
+
+    pub fn builtin(op: FunctionOp, args: Vec<Expr>) -> Expr {
+        Expr::Builtin(Box::new(Builtin { op, args }))
+    }
+
+    pub fn interval(lo: Expr, hi: Expr) -> Expr {
+        Expr::Interval(Box::new(Interval { lo, hi }))
+    }
+
+    pub fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Call(Box::new(Call {
+            name: String::from(name),
+            args,
+        }))
     }
 }
 
-impl Binary {
-    pub fn eval(&self) -> EvalResult {
-        let left = self.lhs.eval()?;
-        let right = self.rhs.eval()?;
-        match self.op {
-            BinaryOp::Add => left.combine_quantity_terms(
-                &right,
-                |a, b| a.auto_checked_binary_op(b, |x, y| x.checked_add(*y), |x, y| *x + *y),
-                "cannot add two quantities with different units",
+// First-order propagation of an uncertainty u in the input x, where shifted(h) evaluates the
+// function with its input changed by h. The derivative is a central difference over a small
+// step, so it is undefined at the edge of the function's domain
+fn propagate_uncertainty<F: Fn(f64) -> Option<f64>>(
+    shifted: F,
+    x: f64,
+    u: f64,
+) -> Result<f64, EvalError> {
+    if u == 0.0 {
+        return Ok(0.0);
+    }
+    let h = f64::EPSILON.cbrt() * x.abs().max(u);
+    let shifted = |h| shifted(h).filter(|y: &f64| y.is_finite());
+    match (shifted(h), shifted(-h)) {
+        (Some(hi), Some(lo)) => Ok(((hi - lo) / (2.0 * h)).abs() * u),
+        _ => Err(EvalError {
+            error: String::from(
+                "cannot propagate the uncertainty at the edge of the function's domain",
             ),
-            BinaryOp::Sub => left.combine_quantity_terms(
-                &right,
-                |a, b| a.auto_checked_binary_op(b, |x, y| x.checked_sub(*y), |x, y| *x - *y),
-                "cannot subtract two quantities with different units",
+        }),
+    }
+}
+
+fn shift_value(quantity: &Quantity, h: f64) -> Quantity {
+    Quantity {
+        value: quantity.value.auto_add(&AutoNum::Float(h)),
+        uncertainty: None,
+        ..quantity.clone()
+    }
+}
+
+impl Builtin {
+    fn check_same_units(&self, args: &[Quantity]) -> Result<(), EvalError> {
+        let expected = &args[0].units;
+        match args.iter().position(|arg| arg.units != *expected) {
+            Some(i) => Err(EvalError {
+                error: format!(
+                    "arguments of '{}' must have the same units (argument {} has units {}, expected {})",
+                    self.op.name(),
+                    i + 1,
+                    args[i].units.to_si_units_str(),
+                    expected.to_si_units_str()
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn eval(&self, env: &Environment) -> EvalResult {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.eval(env))
+            .collect::<Result<Vec<Quantity>, EvalError>>()?;
+        if args
+            .iter()
+            .any(|arg| arg.value.is_complex() || arg.value.is_interval())
+        {
+            return Err(EvalError {
+                error: format!("'{}' is only defined for real arguments", self.op.name()),
+            });
+        }
+
+        let result = self.eval_args(args.clone())?;
+        if args.iter().all(|arg| arg.uncertainty.is_none()) {
+            return Ok(result);
+        }
+        let mut uncertainty: f64 = 0.0;
+        for (i, arg) in args.iter().enumerate() {
+            let Some(u) = arg.uncertainty else {
+                continue;
+            };
+            let shifted = |h| {
+                let mut shifted_args = args.clone();
+                shifted_args[i] = shift_value(arg, h);
+                self.eval_args(shifted_args).ok().map(|y| y.value.cast())
+            };
+            uncertainty = uncertainty.hypot(propagate_uncertainty(shifted, arg.value.cast(), u)?);
+        }
+        result.with_uncertainty(Some(uncertainty))
+    }
+
+    fn eval_args(&self, mut args: Vec<Quantity>) -> EvalResult {
+        match self.op {
+            FunctionOp::LogBase => {
+                if !args.iter().all(|arg| arg.units.no_units()) {
+                    return Err(EvalError {
+                        error: String::from("cannot take the logarithm of a quantity with units"),
+                    });
+                }
+                let base = args[1].value.cast();
+                if base <= 0.0 || base == 1.0 {
+                    return Err(EvalError {
+                        error: String::from("Logarithm base must be positive and not equal to 1"),
+                    });
+                }
+                let value = args[0].value.auto_positive_only(
+                    |y| y.ln(),
+                    false,
+                    "Cannot take the logarithm of a non-positive number",
+                )?;
+                Ok(Quantity::dimensionless(value.cast_then(|y| y / base.ln())))
+            }
+            FunctionOp::Atan2 => {
+                self.check_same_units(&args)?;
+                let (y, x) = (args[0].value.cast(), args[1].value.cast());
+                Ok(Quantity::dimensionless(AutoNum::Float(y.atan2(x))))
+            }
+            FunctionOp::Hypot => {
+                self.check_same_units(&args)?;
+                let sum_squares = args.iter().map(|arg| arg.value.cast().powi(2)).sum::<f64>();
+                Ok(Quantity::new(
+                    AutoNum::Float(sum_squares.sqrt()),
+                    args[0].units.clone(),
+                ))
+            }
+            FunctionOp::Min | FunctionOp::Max => {
+                self.check_same_units(&args)?;
+                let pick_max = matches!(self.op, FunctionOp::Max);
+                let mut picked = &args[0];
+                for arg in &args[1..] {
+                    let (x, current) = (arg.value.cast(), picked.value.cast());
+                    if (pick_max && x > current) || (!pick_max && x < current) {
+                        picked = arg;
+                    }
+                }
+                Ok(picked.clone())
+            }
+            FunctionOp::Clamp => {
+                self.check_same_units(&args)?;
+                let (x, lo, hi) = (
+                    args[0].value.cast(),
+                    args[1].value.cast(),
+                    args[2].value.cast(),
+                );
+                if lo > hi {
+                    return Err(EvalError {
+                        error: String::from(
+                            "lower bound of 'clamp' is greater than the upper bound",
+                        ),
+                    });
+                }
+                if x < lo {
+                    Ok(args.swap_remove(1))
+                } else if x > hi {
+                    Ok(args.swap_remove(2))
+                } else {
+                    Ok(args.swap_remove(0))
+                }
+            }
+        }
+    }
+}
+
+impl Interval {
+    pub fn eval(&self, env: &Environment) -> EvalResult {
+        let lo = self.lo.eval(env)?;
+        let hi = self.hi.eval(env)?;
+        if lo.units != hi.units {
+            return Err(EvalError {
+                error: String::from("bounds of an interval must have the same units"),
+            });
+        }
+        let (lo_value, hi_value) = (lo.value.cast(), hi.value.cast());
+        if lo_value.is_nan() || hi_value.is_nan() {
+            return Err(EvalError {
+                error: String::from("bounds of an interval must be real numbers"),
+            });
+        } else if lo_value > hi_value {
+            return Err(EvalError {
+                error: String::from("lower bound of interval is greater than the upper bound"),
+            });
+        }
+        // the bounds are taken to be exact as given
+        Ok(Quantity::new(
+            AutoNum::Interval(lo_value, hi_value),
+            lo.units,
+        ))
+    }
+}
+
+// Tests whether offset + k * period lies in [lo, hi] for some integer k
+fn contains_periodic(lo: f64, hi: f64, offset: f64, period: f64) -> bool {
+    offset + ((lo - offset) / period).ceil() * period <= hi
+}
+
+// Functions can be redefined to call each other, so calls are limited rather than left to
+// overflow the stack
+const MAX_CALL_DEPTH: usize = 200;
+const CALL_DEPTH_ERROR: &str = "function calls are nested deeper";
+
+impl Call {
+    pub fn eval(&self, env: &Environment) -> EvalResult {
+        let Some(closure) = env.get_function(&self.name) else {
+            return Err(EvalError {
+                error: format!("function '{}' is not defined", self.name),
+            });
+        };
+
+        if env.call_depth() >= MAX_CALL_DEPTH {
+            return Err(EvalError {
+                error: format!("{CALL_DEPTH_ERROR} than {MAX_CALL_DEPTH}"),
+            });
+        }
+
+        let function = &closure.function;
+        let mut local_env = env.with_variables(closure.captured.clone());
+        for (i, (param, arg)) in function.params.iter().zip(&self.args).enumerate() {
+            let value = arg.eval(env)?;
+            if let Some((units_str, units)) = &param.units {
+                if value.units != units.units {
+                    return Err(EvalError {
+                        error: format!(
+                            "argument {} ('{}') of '{}' must have units of {units_str}, found {}",
+                            i + 1,
+                            param.name,
+                            self.name,
+                            value.units.to_si_units_str()
+                        ),
+                    });
+                }
+            }
+            local_env.set_variable(&param.name, value);
+        }
+
+        // The depth error is passed up unchanged rather than naming every call on the way
+        function
+            .body
+            .eval(&local_env)
+            .map_err(|e| match e.error.starts_with(CALL_DEPTH_ERROR) {
+                true => e,
+                false => EvalError {
+                    error: format!("in '{}': {}", self.name, e.error),
+                },
+            })
+    }
+}
+
+// Poles repeat every pi from the offset. Angles such as 90 deg are rounded, so a pole is
+// any angle within a few rounding errors of its own size from one. Only 0 itself counts
+// at 0, since small angles are exact, and angles too large to tell poles apart have none
+fn is_trig_pole(angle: f64, offset: f64) -> bool {
+    let pole = offset + ((angle - offset) / PI).round() * PI;
+    let tolerance = 4.0 * f64::EPSILON * angle.abs();
+    tolerance < FRAC_PI_4 && (angle - pole).abs() <= tolerance
+}
+
+impl Binary {
+    // First-order propagation of independent uncertainties, from the partial derivatives
+    // of each operation
+    fn propagate_uncertainty(
+        &self,
+        left: &Quantity,
+        right: &Quantity,
+        result: &Quantity,
+    ) -> Option<f64> {
+        if left.uncertainty.is_none() && right.uncertainty.is_none() {
+            return None;
+        }
+        let (a, b) = (left.value.cast(), right.value.cast());
+        let (ua, ub) = (
+            left.uncertainty.unwrap_or(0.0),
+            right.uncertainty.unwrap_or(0.0),
+        );
+        let (da, db) = match self.op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::PlusMinus => (ua, ub),
+            // integer operators reject uncertain operands
+            BinaryOp::IntDiv
+            | BinaryOp::Mod
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::Shl
+            | BinaryOp::Shr => return None,
+            BinaryOp::Mul => (b * ua, a * ub),
+            BinaryOp::Div => (ua / b, a * ub / (b * b)),
+            BinaryOp::Pow => (
+                if ua == 0.0 {
+                    0.0
+                } else {
+                    b * a.powf(b - 1.0) * ua
+                },
+                if ub == 0.0 {
+                    0.0
+                } else {
+                    result.value.cast() * a.ln() * ub
+                },
             ),
+        };
+        Some(da.hypot(db))
+    }
+
+    fn plus_minus(value: Quantity, uncertainty: &Quantity) -> EvalResult {
+        if value.units != uncertainty.units {
+            return Err(EvalError {
+                error: String::from("uncertainty must have the same units as the value"),
+            });
+        }
+        let u = uncertainty.value.cast();
+        if u.is_nan() || u < 0.0 {
+            return Err(EvalError {
+                error: String::from("uncertainty must be a non-negative real number"),
+            });
+        }
+        // combined with any existing uncertainty of the value
+        let combined = value.uncertainty.unwrap_or(0.0).hypot(u);
+        value.with_uncertainty(Some(combined))
+    }
+
+    // Integer division and modulo round towards negative infinity, and shifts are arithmetic
+    fn integer_op(&self, left: &Quantity, right: &Quantity) -> EvalResult {
+        let symbol = self.op.symbol();
+        let (a, b) = match left.value.as_big_int().zip(right.value.as_big_int()) {
+            Some(operands)
+                if left.units.no_units()
+                    && right.units.no_units()
+                    && left.uncertainty.is_none()
+                    && right.uncertainty.is_none() =>
+            {
+                operands
+            }
+            _ => {
+                return Err(EvalError {
+                    error: format!("'{symbol}' is only defined for dimensionless integers"),
+                });
+            }
+        };
+        if b.is_negative() && matches!(self.op, BinaryOp::Shl | BinaryOp::Shr) {
+            return Err(EvalError {
+                error: String::from("Cannot shift by a negative amount"),
+            });
+        }
+
+        let result = match self.op {
+            BinaryOp::IntDiv | BinaryOp::Mod => {
+                let (quotient, remainder) = a.div_rem_floor(&b).ok_or_else(|| EvalError {
+                    error: String::from("Division by 0"),
+                })?;
+                match self.op {
+                    BinaryOp::IntDiv => quotient,
+                    _ => remainder,
+                }
+            }
+            BinaryOp::BitAnd => a.bitwise(&b, |x, y| x & y),
+            BinaryOp::BitOr => a.bitwise(&b, |x, y| x | y),
+            BinaryOp::BitXor => a.bitwise(&b, |x, y| x ^ y),
+            BinaryOp::Shl if a.is_zero() => a,
+            BinaryOp::Shl => {
+                // past MAX_DIGITS the power falls back to a float, which a shift cannot give
+                let factor = AutoNum::Int(2).auto_pow(&right.value)?;
+                return match left.value.auto_mul(&factor) {
+                    result @ (AutoNum::Int(_) | AutoNum::Big(_)) => {
+                        Ok(Quantity::dimensionless(result))
+                    }
+                    _ => Err(EvalError {
+                        error: format!("Result of '<<' has more than {MAX_DIGITS} digits"),
+                    }),
+                };
+            }
+            BinaryOp::Shr => a.shr(b.to_i64().map_or(u64::MAX, |n| n as u64)),
+            _ => unreachable!(),
+        };
+        Ok(Quantity::dimensionless(AutoNum::from_big_int(result)))
+    }
+
+    pub fn eval(&self, env: &Environment) -> EvalResult {
+        let left = self.lhs.eval(env)?;
+        let right = self.rhs.eval(env)?;
+        if left.value.is_interval() && right.value.is_complex()
+            || left.value.is_complex() && right.value.is_interval()
+        {
+            return Err(EvalError {
+                error: String::from("intervals cannot be combined with complex numbers"),
+            });
+        }
+        let result = match self.op {
+            BinaryOp::Add => Ok(left
+                .combine_quantity_terms(
+                    &right,
+                    |a, b| a.auto_add(b),
+                    "cannot add two quantities with different units",
+                )?
+                .with_temperature(left.added_temperature_kind(&right, false)?)),
+            BinaryOp::Sub => Ok(left
+                .combine_quantity_terms(
+                    &right,
+                    |a, b| a.auto_sub(b),
+                    "cannot subtract two quantities with different units",
+                )?
+                .with_temperature(left.added_temperature_kind(&right, true)?)),
             BinaryOp::Mul => left.mul_quantity(&right),
             BinaryOp::Div => Ok(Quantity::new(
                 left.value.auto_div(&right.value)?,
                 left.units.combine(&right.units, true)?,
             )),
-            BinaryOp::Pow => left.pow_quantity(&right),
-        }
+            BinaryOp::Pow => {
+                let result = left.pow_quantity(&right)?;
+                // negative bases with non-integer exponents only have complex results
+                match result.value {
+                    AutoNum::Float(x) if x.is_nan() && env.is_complex() => Ok(Quantity::new(
+                        left.value.auto_complex_pow(&right.value),
+                        result.units,
+                    )),
+                    _ => Ok(result),
+                }
+            }
+            BinaryOp::PlusMinus => return Binary::plus_minus(left, &right),
+            BinaryOp::IntDiv
+            | BinaryOp::Mod
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::Shl
+            | BinaryOp::Shr => return self.integer_op(&left, &right),
+        }?;
+        let uncertainty = self.propagate_uncertainty(&left, &right, &result);
+        result.with_uncertainty(uncertainty)
     }
 }
 
 impl Unary {
-    pub fn eval(&self) -> EvalResult {
-        let operand_result = self.operand.eval()?;
+    fn complex_function(&self) -> Option<fn(Complex) -> Complex> {
         match self.op {
-            UnaryOp::Positive => Ok(operand_result),
-            UnaryOp::Negative => Ok(Quantity::new(
-                operand_result.value.auto_checked_binary_op(
-                    &AutoNum::Int(0),
-                    |x, _| x.checked_neg(),
-                    |x, _| -x,
+            UnaryOp::Sin => Some(Complex::sin),
+            UnaryOp::Cos => Some(Complex::cos),
+            UnaryOp::Tan => Some(Complex::tan),
+            UnaryOp::Sec => Some(|z| Complex::new(1.0, 0.0).div(z.cos())),
+            UnaryOp::Csc => Some(|z| Complex::new(1.0, 0.0).div(z.sin())),
+            UnaryOp::Cot => Some(|z| Complex::new(1.0, 0.0).div(z.tan())),
+            UnaryOp::Asin => Some(Complex::asin),
+            UnaryOp::Acos => Some(Complex::acos),
+            UnaryOp::Atan => Some(Complex::atan),
+            UnaryOp::Sinh => Some(Complex::sinh),
+            UnaryOp::Cosh => Some(Complex::cosh),
+            UnaryOp::Tanh => Some(Complex::tanh),
+            UnaryOp::Asinh => Some(Complex::asinh),
+            UnaryOp::Acosh => Some(Complex::acosh),
+            UnaryOp::Atanh => Some(Complex::atanh),
+            UnaryOp::Exp => Some(Complex::exp),
+            UnaryOp::Ln => Some(Complex::ln),
+            UnaryOp::Log => Some(Complex::log10),
+            _ => None,
+        }
+    }
+
+    pub fn eval(&self, env: &Environment) -> EvalResult {
+        let operand_result = self.operand.eval(env)?;
+        let result = self.eval_value(&operand_result, env)?;
+        let Some(u) = operand_result.uncertainty else {
+            return Ok(result);
+        };
+        let shifted = |h| {
+            self.eval_real(shift_value(&operand_result, h))
+                .ok()
+                .map(|y| y.value.cast())
+        };
+        let uncertainty = propagate_uncertainty(shifted, operand_result.value.cast(), u)?;
+        result.with_uncertainty(Some(uncertainty))
+    }
+
+    // Angles where the function has poles, which repeat every pi, and the angles and values
+    // of its turning points, which repeat every 2 pi
+    fn periodic_critical_points(&self) -> (Option<f64>, &'static [(f64, f64)]) {
+        const TURNING_SIN: [(f64, f64); 2] = [(FRAC_PI_2, 1.0), (-FRAC_PI_2, -1.0)];
+        const TURNING_COS: [(f64, f64); 2] = [(0.0, 1.0), (PI, -1.0)];
+        match self.op {
+            UnaryOp::Sin => (None, &TURNING_SIN),
+            UnaryOp::Cos => (None, &TURNING_COS),
+            UnaryOp::Tan => (Some(FRAC_PI_2), &[]),
+            UnaryOp::Sec => (Some(FRAC_PI_2), &TURNING_COS),
+            UnaryOp::Csc => (Some(0.0), &TURNING_SIN),
+            UnaryOp::Cot => (Some(0.0), &[]),
+            _ => (None, &[]),
+        }
+    }
+
+    // Bounds come from evaluating the function at both ends of the interval, which is
+    // enough for monotonic functions. Otherwise any turning points inside the interval are
+    // included, and poles inside the interval are an error
+    fn eval_interval(&self, operand: &Quantity, lo: f64, hi: f64) -> EvalResult {
+        if matches!(self.op, UnaryOp::Factorial) {
+            return Err(EvalError {
+                error: String::from("Cannot take the factorial of an interval"),
+            });
+        }
+        let at = |x: f64| {
+            self.eval_real(Quantity {
+                value: AutoNum::Float(x),
+                ..operand.clone()
+            })
+        };
+        let (lo_result, hi_result) = (at(lo)?, at(hi)?);
+
+        // The function values at the ends are rounded, so they are widened by an ulp. Zeros
+        // are kept, since these functions are only 0 at exact points, apart from exp underflowing
+        let widened = |x: f64| match x == 0.0 && !matches!(self.op, UnaryOp::Exp) {
+            true => (x, x),
+            false => (x.next_down(), x.next_up()),
+        };
+        let mut values = vec![
+            widened(lo_result.value.cast()),
+            widened(hi_result.value.cast()),
+        ];
+
+        let (pole, turning_points) = self.periodic_critical_points();
+        if pole.is_some_and(|pole| contains_periodic(lo, hi, pole, PI)) {
+            return Err(EvalError {
+                error: String::from(
+                    "Cannot evaluate the function over an interval containing a pole",
                 ),
-                operand_result.units.clone(),
+            });
+        }
+        for &(angle, value) in turning_points {
+            if contains_periodic(lo, hi, angle, TAU) {
+                values.push((value, value));
+            }
+        }
+        if matches!(self.op, UnaryOp::Cosh) && lo < 0.0 && hi > 0.0 {
+            values.push((1.0, 1.0));
+        }
+
+        if values.iter().any(|(min, max)| min.is_nan() || max.is_nan()) {
+            return Ok(Quantity::new(AutoNum::Float(f64::NAN), lo_result.units));
+        }
+        let (min, max) = values
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &(x, y)| {
+                (min.min(x), max.max(y))
+            });
+        Ok(Quantity::new(AutoNum::interval(min, max), lo_result.units))
+    }
+
+    // Functions are evaluated over the complex numbers for complex operands, or in
+    // complex mode when a real operand is outside of the function's real domain
+    fn eval_value(&self, operand_result: &Quantity, env: &Environment) -> EvalResult {
+        if let &AutoNum::Interval(lo, hi) = &operand_result.value {
+            return match self.op {
+                UnaryOp::Positive | UnaryOp::Negative | UnaryOp::Percent | UnaryOp::Undim => {
+                    self.eval_real(operand_result.clone())
+                }
+                _ => self.eval_interval(operand_result, lo, hi),
+            };
+        }
+        let is_complex = operand_result.value.is_complex();
+        let real_result = self.eval_real(operand_result.clone());
+        if !(is_complex || real_result.is_err() && env.is_complex()) {
+            return real_result;
+        }
+
+        match (&self.op, self.complex_function()) {
+            (&UnaryOp::RootN(n), _) if !is_complex => Ok(Quantity::new(
+                AutoNum::from_complex(operand_result.value.as_complex().root_n(n)),
+                operand_result.units.root(n)?,
             )),
+            (_, Some(f)) if operand_result.units.no_units() => Ok(Quantity::dimensionless(
+                AutoNum::from_complex(f(operand_result.value.as_complex())),
+            )),
+            _ => real_result,
+        }
+    }
+
+    fn eval_real(&self, operand_result: Quantity) -> EvalResult {
+        match self.op {
+            UnaryOp::Positive => Ok(operand_result),
+            UnaryOp::Negative => {
+                let temperature = match operand_result.temperature {
+                    TemperatureKind::Difference => TemperatureKind::Difference,
+                    _ => TemperatureKind::Plain,
+                };
+                Ok(Quantity::new(
+                    operand_result.value.auto_neg(),
+                    operand_result.units.clone(),
+                )
+                .with_temperature(temperature))
+            }
             UnaryOp::RootN(n) => Ok(Quantity::new(
                 operand_result.value.auto_root_n(n)?,
                 operand_result.units.root(n)?,
             )),
+            UnaryOp::BitNot => operand_result.unitless_op(
+                |x| match x.as_big_int() {
+                    Some(n) => Ok(AutoNum::from_big_int(n.not())),
+                    None => Err(EvalError {
+                        error: String::from("'~' is only defined for integers"),
+                    }),
+                },
+                "'~' is only defined for dimensionless integers",
+            ),
             UnaryOp::Percent => operand_result.unitless_op(
-                |x| Ok(x.cast_then(|y| y / 100.0)),
+                |x| x.auto_div(&AutoNum::Int(100)),
                 "cannot use percentage on quantity with units, consider using x / 100 instead",
             ),
             UnaryOp::Factorial => operand_result.unitless_op(
@@ -119,8 +816,104 @@ impl Unary {
                 "cannot take the cosine of quantity with units (degrees are dimensionless)",
             ),
             UnaryOp::Tan => operand_result.unitless_op(
-                |x| Ok(x.cast_then(|y| y.tan())),
-                "cannot take the cosine of quantity with units (degrees are dimensionless)",
+                |x| {
+                    x.auto_in_domain(
+                        |y| y.tan(),
+                        |y| !is_trig_pole(*y, FRAC_PI_2),
+                        "Cannot take the tangent where the cosine is 0",
+                    )
+                },
+                "cannot take the tangent of quantity with units (degrees are dimensionless)",
+            ),
+            UnaryOp::Sec => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| 1.0 / y.cos(),
+                        |y| !is_trig_pole(*y, FRAC_PI_2),
+                        "Cannot take the secant where the cosine is 0",
+                    )
+                },
+                "cannot take the secant of quantity with units (degrees are dimensionless)",
+            ),
+            UnaryOp::Csc => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| 1.0 / y.sin(),
+                        |y| !is_trig_pole(*y, 0.0),
+                        "Cannot take the cosecant where the sine is 0",
+                    )
+                },
+                "cannot take the cosecant of quantity with units (degrees are dimensionless)",
+            ),
+            UnaryOp::Cot => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| 1.0 / y.tan(),
+                        |y| !is_trig_pole(*y, 0.0),
+                        "Cannot take the cotangent where the tangent is 0",
+                    )
+                },
+                "cannot take the cotangent of quantity with units (degrees are dimensionless)",
+            ),
+            UnaryOp::Asin => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| y.asin(),
+                        |y| (-1.0..=1.0).contains(y),
+                        "Cannot take the arcsine of a number outside [-1, 1]",
+                    )
+                },
+                "cannot take the arcsine of a quantity with units",
+            ),
+            UnaryOp::Acos => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| y.acos(),
+                        |y| (-1.0..=1.0).contains(y),
+                        "Cannot take the arccosine of a number outside [-1, 1]",
+                    )
+                },
+                "cannot take the arccosine of a quantity with units",
+            ),
+            UnaryOp::Atan => operand_result.unitless_op(
+                |x| Ok(x.cast_then(|y| y.atan())),
+                "cannot take the arctangent of a quantity with units",
+            ),
+            UnaryOp::Sinh => operand_result.unitless_op(
+                |x| Ok(x.cast_then(|y| y.sinh())),
+                "cannot take the hyperbolic sine of a quantity with units",
+            ),
+            UnaryOp::Cosh => operand_result.unitless_op(
+                |x| Ok(x.cast_then(|y| y.cosh())),
+                "cannot take the hyperbolic cosine of a quantity with units",
+            ),
+            UnaryOp::Tanh => operand_result.unitless_op(
+                |x| Ok(x.cast_then(|y| y.tanh())),
+                "cannot take the hyperbolic tangent of a quantity with units",
+            ),
+            UnaryOp::Asinh => operand_result.unitless_op(
+                |x| Ok(x.cast_then(|y| y.asinh())),
+                "cannot take the inverse hyperbolic sine of a quantity with units",
+            ),
+            UnaryOp::Acosh => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| y.acosh(),
+                        |y| *y >= 1.0,
+                        "Cannot take the inverse hyperbolic cosine of a number less than 1",
+                    )
+                },
+                "cannot take the inverse hyperbolic cosine of a quantity with units",
+            ),
+            UnaryOp::Atanh => operand_result.unitless_op(
+                |x| {
+                    x.auto_in_domain(
+                        |y| y.atanh(),
+                        |y| *y > -1.0 && *y < 1.0,
+                        "Cannot take the inverse hyperbolic tangent of a number outside (-1, 1)",
+                    )
+                },
+                "cannot take the inverse hyperbolic tangent of a quantity with units",
             ),
             UnaryOp::Exp => operand_result.unitless_op(
                 |x| Ok(x.cast_then(|y| y.exp())),
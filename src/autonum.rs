@@ -1,18 +1,226 @@
-use crate::ast::EvalError;
+use crate::{ast::EvalError, bigint::BigInt, complex::Complex};
 
 #[derive(Clone, Debug)]
 pub enum AutoNum {
     Int(i64),
+    // Always stored in lowest terms with a denominator greater than 1
+    Rational(i64, i64),
     Float(f64),
+    // Only used for integers that do not fit in an i64
+    Big(BigInt),
+    // Real and imaginary parts, the imaginary part is never 0
+    Complex(f64, f64),
+    // Closed interval, rounded outwards after every operation so that it is guaranteed
+    // to contain the exact result
+    Interval(f64, f64),
 }
 
 pub type AutoNumResult = Result<AutoNum, EvalError>;
 
+type Fraction = (i128, i128);
+type Bounds = (f64, f64);
+
+// Exact integer results with more digits than this fall back to floating point
+pub const MAX_DIGITS: usize = 50_000;
+
+// Encloses the exact result of an operation, given the rounded result x and the sign of
+// its rounding error (the exact result minus x), which is NaN when it is not known
+fn enclose(x: f64, error: f64) -> Bounds {
+    if error == 0.0 {
+        (x, x)
+    } else if error > 0.0 {
+        (x, x.next_up())
+    } else if error < 0.0 {
+        (x.next_down(), x)
+    } else {
+        (x.next_down(), x.next_up())
+    }
+}
+
+// The error of a product or quotient from a fused multiply-add is only exact while it is
+// far enough above the subnormal range not to underflow
+fn has_exact_error(x: f64) -> bool {
+    x.is_finite() && x.abs() >= f64::MIN_POSITIVE * 2f64.powi(54)
+}
+
+// The rounding error of a sum can always be found exactly, unless it overflows
+fn add_bounds(a: f64, b: f64) -> Bounds {
+    let sum = a + b;
+    if a.is_infinite() || b.is_infinite() {
+        return (sum, sum);
+    }
+    let b_part = sum - a;
+    let error = (a - (sum - b_part)) + (b - b_part);
+    enclose(sum, if sum.is_finite() { error } else { f64::NAN })
+}
+
+// Products and quotients that underflowed to 0 keep the sign of the exact result
+fn mul_bounds(a: f64, b: f64) -> Bounds {
+    let product = a * b;
+    if a == 0.0 || b == 0.0 || a.is_infinite() || b.is_infinite() {
+        (product, product)
+    } else if product == 0.0 {
+        enclose(product, 1f64.copysign(product))
+    } else if has_exact_error(product) {
+        enclose(product, a.mul_add(b, -product))
+    } else {
+        enclose(product, f64::NAN)
+    }
+}
+
+fn div_bounds(a: f64, b: f64) -> Bounds {
+    let quotient = a / b;
+    if a == 0.0 || a.is_infinite() || b.is_infinite() {
+        (quotient, quotient)
+    } else if quotient == 0.0 {
+        enclose(quotient, 1f64.copysign(quotient))
+    } else if has_exact_error(quotient) && has_exact_error(a) {
+        enclose(quotient, -quotient.mul_add(b, -a) * b.signum())
+    } else {
+        enclose(quotient, f64::NAN)
+    }
+}
+
+// powf is not correctly rounded, so only trivial powers and whole powers of whole numbers
+// that are small enough to be represented exactly are taken to be exact
+fn pow_bounds(a: f64, c: f64) -> Bounds {
+    let power = a.powf(c);
+    let exact = a == 0.0
+        || a == 1.0
+        || c == 0.0
+        || c == 1.0
+        || a.is_infinite()
+        || c.is_infinite()
+        || a.fract() == 0.0 && c.fract() == 0.0 && c > 0.0 && power.abs() < 2f64.powi(53);
+    if exact {
+        (power, power)
+    } else if power == 0.0 {
+        enclose(power, 1f64.copysign(power))
+    } else {
+        enclose(power, f64::NAN)
+    }
+}
+
+// The smallest interval containing all of the enclosures. An undefined value such as
+// 0 * inf makes the whole interval undefined
+fn interval_bounds(values: &[Bounds]) -> Bounds {
+    if values.iter().any(|(lo, hi)| lo.is_nan() || hi.is_nan()) {
+        return (f64::NAN, f64::NAN);
+    }
+    values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(x, y)| {
+            (lo.min(x), hi.max(y))
+        })
+}
+
+fn gcd(m: i128, n: i128) -> i128 {
+    match n {
+        0 => m,
+        _ => gcd(n, m % n),
+    }
+}
+
+// Factorials are exact integers, so there is no float to fall back to past MAX_DIGITS
+fn factorial_too_large() -> EvalError {
+    EvalError {
+        error: format!("Result of '!' has more than {MAX_DIGITS} digits"),
+    }
+}
+
 impl AutoNum {
+    // Reduces n/d to lowest terms, or gives None if it cannot be represented with i64s
+    pub fn rational(n: i128, d: i128) -> Option<AutoNum> {
+        let divisor = gcd(n.abs(), d.abs()) * d.signum();
+        if divisor == 0 {
+            return None;
+        }
+        let numerator = i64::try_from(n / divisor).ok()?;
+        match d / divisor {
+            1 => Some(AutoNum::Int(numerator)),
+            denominator => Some(AutoNum::Rational(
+                numerator,
+                i64::try_from(denominator).ok()?,
+            )),
+        }
+    }
+
+    pub fn from_big_int(n: BigInt) -> AutoNum {
+        match n.to_i64() {
+            Some(n) => AutoNum::Int(n),
+            None => AutoNum::Big(n),
+        }
+    }
+
+    pub fn from_complex(z: Complex) -> AutoNum {
+        if z.im == 0.0 {
+            AutoNum::Float(z.re)
+        } else {
+            AutoNum::Complex(z.re, z.im)
+        }
+    }
+
+    // The bounds have already been rounded outwards by the operation that produced them
+    pub fn interval(lo: f64, hi: f64) -> AutoNum {
+        if lo.is_nan() || hi.is_nan() {
+            AutoNum::Float(f64::NAN)
+        } else {
+            AutoNum::Interval(lo, hi)
+        }
+    }
+
+    fn as_fraction(&self) -> Option<Fraction> {
+        match *self {
+            AutoNum::Int(n) => Some((n as i128, 1)),
+            AutoNum::Rational(n, d) => Some((n as i128, d as i128)),
+            AutoNum::Float(_) | AutoNum::Big(_) | AutoNum::Complex(..) | AutoNum::Interval(..) => {
+                None
+            }
+        }
+    }
+
+    pub fn as_big_int(&self) -> Option<BigInt> {
+        match self {
+            &AutoNum::Int(n) => Some(BigInt::from_i128(n as i128)),
+            AutoNum::Big(n) => Some(n.clone()),
+            AutoNum::Rational(..)
+            | AutoNum::Float(_)
+            | AutoNum::Complex(..)
+            | AutoNum::Interval(..) => None,
+        }
+    }
+
+    pub fn as_complex(&self) -> Complex {
+        match *self {
+            AutoNum::Complex(re, im) => Complex::new(re, im),
+            _ => Complex::new(self.cast(), 0.0),
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(self, AutoNum::Complex(..))
+    }
+
+    pub fn as_interval(&self) -> Bounds {
+        match *self {
+            AutoNum::Interval(lo, hi) => (lo, hi),
+            _ => (self.cast(), self.cast()),
+        }
+    }
+
+    pub fn is_interval(&self) -> bool {
+        matches!(self, AutoNum::Interval(..))
+    }
+
     pub fn cast(&self) -> f64 {
         match self {
             &AutoNum::Int(n) => n as f64,
+            &AutoNum::Rational(n, d) => n as f64 / d as f64,
             &AutoNum::Float(x) => x,
+            AutoNum::Big(n) => n.to_f64(),
+            // complex numbers and intervals have no single real value, so real-only
+            // functions give NaN
+            AutoNum::Complex(..) | AutoNum::Interval(..) => f64::NAN,
         }
     }
 
@@ -20,62 +228,158 @@ impl AutoNum {
         AutoNum::Float(f(&self.cast()))
     }
 
-    pub fn auto_checked_binary_op(
+    // Exact arithmetic for integers and fractions, using big integers if the result
+    // of integer arithmetic overflows. Falls back to floating point if either operand
+    // is a float or the exact result cannot be represented
+    pub fn auto_exact_binary_op(
         &self,
         other: &AutoNum,
-        checked_op: fn(&i64, &i64) -> Option<i64>,
+        exact_op: fn(Fraction, Fraction) -> Option<Fraction>,
+        big_op: fn(&BigInt, &BigInt) -> Option<AutoNum>,
+        complex_op: fn(Complex, Complex) -> Complex,
+        interval_op: fn(Bounds, Bounds) -> Bounds,
         fallback: fn(&f64, &f64) -> f64,
     ) -> AutoNum {
-        match (self, other) {
-            (AutoNum::Int(left), AutoNum::Int(right)) => match checked_op(left, right) {
-                Some(result) => AutoNum::Int(result),
-                None => AutoNum::Float(fallback(&(*left as f64), &(*right as f64))),
-            },
-            _ => AutoNum::Float(fallback(&self.cast(), &other.cast())),
+        if self.is_interval() || other.is_interval() {
+            let (lo, hi) = interval_op(self.as_interval(), other.as_interval());
+            return AutoNum::interval(lo, hi);
+        }
+        if self.is_complex() || other.is_complex() {
+            return AutoNum::from_complex(complex_op(self.as_complex(), other.as_complex()));
         }
+        self.as_fraction()
+            .zip(other.as_fraction())
+            .and_then(|(a, b)| exact_op(a, b))
+            .and_then(|(n, d)| AutoNum::rational(n, d))
+            .or_else(|| {
+                let (a, b) = self.as_big_int().zip(other.as_big_int())?;
+                big_op(&a, &b)
+            })
+            .unwrap_or_else(|| AutoNum::Float(fallback(&self.cast(), &other.cast())))
+    }
+
+    pub fn auto_add(&self, other: &AutoNum) -> AutoNum {
+        self.auto_exact_binary_op(
+            other,
+            |(a, b), (c, d)| Some((a.checked_mul(d)?.checked_add(c.checked_mul(b)?)?, b * d)),
+            |a, b| Some(AutoNum::from_big_int(a.add(b))),
+            Complex::add,
+            |(a, b), (c, d)| (add_bounds(a, c).0, add_bounds(b, d).1),
+            |a, b| *a + *b,
+        )
+    }
+
+    pub fn auto_sub(&self, other: &AutoNum) -> AutoNum {
+        self.auto_exact_binary_op(
+            other,
+            |(a, b), (c, d)| Some((a.checked_mul(d)?.checked_sub(c.checked_mul(b)?)?, b * d)),
+            |a, b| Some(AutoNum::from_big_int(a.sub(b))),
+            Complex::sub,
+            |(a, b), (c, d)| (add_bounds(a, -d).0, add_bounds(b, -c).1),
+            |a, b| *a - *b,
+        )
     }
 
     pub fn auto_mul(&self, other: &AutoNum) -> AutoNum {
-        self.auto_checked_binary_op(other, |a, b| a.checked_mul(*b), |a, b| *a * *b)
+        self.auto_exact_binary_op(
+            other,
+            |(a, b), (c, d)| Some((a.checked_mul(c)?, b.checked_mul(d)?)),
+            |a, b| Some(AutoNum::from_big_int(a.mul(b))),
+            Complex::mul,
+            |(a, b), (c, d)| {
+                interval_bounds(&[
+                    mul_bounds(a, c),
+                    mul_bounds(a, d),
+                    mul_bounds(b, c),
+                    mul_bounds(b, d),
+                ])
+            },
+            |a, b| *a * *b,
+        )
+    }
+
+    pub fn auto_neg(&self) -> AutoNum {
+        self.auto_exact_binary_op(
+            &AutoNum::Int(0),
+            |(a, b), _| Some((-a, b)),
+            |a, _| Some(AutoNum::from_big_int(a.neg())),
+            |z, _| z.neg(),
+            |(a, b), _| (-b, -a),
+            |x, _| -x,
+        )
     }
 
     pub fn auto_div(&self, other: &AutoNum) -> AutoNumResult {
-        match (self, other) {
-            (&AutoNum::Int(left), &AutoNum::Int(right)) => {
-                if right == 0 {
-                    Err(EvalError {
-                        error: String::from("Division by 0"),
-                    })
-                } else if left % right == 0 {
-                    Ok(AutoNum::Int(left / right))
-                } else {
-                    Ok(AutoNum::Float((left as f64) / (right as f64)))
-                }
-            }
-            _ => {
-                let denom = other.cast();
-                if denom == 0.0 {
-                    Err(EvalError {
-                        error: String::from("Division by 0"),
-                    })
-                } else {
-                    Ok(self.cast_then(|x| x / denom))
-                }
+        if other.cast() == 0.0 {
+            return Err(EvalError {
+                error: String::from("Division by 0"),
+            });
+        }
+        if let &AutoNum::Interval(lo, hi) = other {
+            if lo <= 0.0 && hi >= 0.0 {
+                return Err(EvalError {
+                    error: String::from("Division by an interval containing 0"),
+                });
             }
         }
+        Ok(self.auto_exact_binary_op(
+            other,
+            |(a, b), (c, d)| Some((a.checked_mul(d)?, b.checked_mul(c)?)),
+            |a, b| {
+                let (quotient, remainder) = a.div_rem(b)?;
+                if remainder.is_zero() {
+                    return Some(AutoNum::from_big_int(quotient));
+                }
+                let divisor = a.gcd(b);
+                let (n, _) = a.div_rem(&divisor)?;
+                let (d, _) = b.div_rem(&divisor)?;
+                AutoNum::rational(n.to_i64()? as i128, d.to_i64()? as i128)
+            },
+            Complex::div,
+            |(a, b), (c, d)| {
+                interval_bounds(&[
+                    div_bounds(a, c),
+                    div_bounds(a, d),
+                    div_bounds(b, c),
+                    div_bounds(b, d),
+                ])
+            },
+            |a, b| *a / *b,
+        ))
     }
 
-    pub fn auto_pow(&self, other: &AutoNum) -> AutoNum {
-        match other {
-            &AutoNum::Int(n) => {
+    pub fn auto_pow(&self, other: &AutoNum) -> AutoNumResult {
+        if self.is_interval() || other.is_interval() {
+            return self.auto_interval_pow(other);
+        }
+        let small_int_exponent = matches!(other, AutoNum::Int(n) if n.abs() < 64);
+        if other.is_complex() || self.is_complex() && !small_int_exponent {
+            return Ok(self.auto_complex_pow(other));
+        }
+
+        Ok(match *other {
+            AutoNum::Int(n) => {
+                if let (Some(base), Ok(exponent)) = (self.as_big_int(), u32::try_from(n)) {
+                    let magnitude = self.cast().abs().log10();
+                    let digits = if magnitude.is_finite() {
+                        magnitude
+                    } else {
+                        base.digit_count() as f64
+                    };
+                    if digits * n as f64 <= MAX_DIGITS as f64 {
+                        return Ok(AutoNum::from_big_int(base.pow(exponent)));
+                    }
+                }
+
                 if n < 64 && n > -64 {
                     let negexp = n < 0;
-                    let product = std::iter::repeat(self)
-                        .take(n.abs() as usize)
+                    let product = std::iter::repeat_n(self, n.unsigned_abs() as usize)
                         .fold(AutoNum::Int(1), |acc, x| acc.auto_mul(x));
 
                     if negexp {
-                        product.cast_then(|x| 1.0 / x)
+                        AutoNum::Int(1)
+                            .auto_div(&product)
+                            .unwrap_or_else(|_| product.cast_then(|x| 1.0 / x))
                     } else {
                         product
                     }
@@ -83,8 +387,60 @@ impl AutoNum {
                     self.cast_then(|x| x.powf(n as f64))
                 }
             }
-            &AutoNum::Float(x) => self.cast_then(|y| y.powf(x)),
+            AutoNum::Rational(..)
+            | AutoNum::Float(_)
+            | AutoNum::Big(_)
+            | AutoNum::Complex(..)
+            | AutoNum::Interval(..) => {
+                let exponent = other.cast();
+                self.cast_then(|y| y.powf(exponent))
+            }
+        })
+    }
+
+    // Powers of positive bases are monotonic in both the base and the exponent, so the
+    // bounds are found at the corners. Bases that include negative numbers only allow
+    // integer exponents, where the result depends on the parity and sign of the exponent.
+    // Negative exponents of bases that include 0 divide by it, as x / [-1, 1] would
+    fn auto_interval_pow(&self, other: &AutoNum) -> AutoNumResult {
+        let (a, b) = self.as_interval();
+        if other.as_interval().0 < 0.0 && a <= 0.0 && b >= 0.0 {
+            return Err(EvalError {
+                error: String::from("Division by an interval containing 0"),
+            });
         }
+        let (lo, hi) = match *other {
+            AutoNum::Int(0) => return Ok(AutoNum::Int(1)),
+            AutoNum::Int(n) if a < 0.0 => {
+                let (x, y) = (pow_bounds(a, n as f64), pow_bounds(b, n as f64));
+                match n % 2 == 0 && b >= 0.0 {
+                    true => (0.0, x.1.max(y.1)),
+                    false => interval_bounds(&[x, y]),
+                }
+            }
+            _ if a < 0.0 => {
+                return Err(EvalError {
+                    error: String::from(
+                        "Cannot raise negative numbers to a non-integer or interval power",
+                    ),
+                });
+            }
+            _ => {
+                let (c, d) = other.as_interval();
+                interval_bounds(&[
+                    pow_bounds(a, c),
+                    pow_bounds(a, d),
+                    pow_bounds(b, c),
+                    pow_bounds(b, d),
+                ])
+            }
+        };
+        Ok(AutoNum::interval(lo, hi))
+    }
+
+    // The principal value, which also allows negative bases with non-integer exponents
+    pub fn auto_complex_pow(&self, other: &AutoNum) -> AutoNum {
+        AutoNum::from_complex(self.as_complex().pow(other.as_complex()))
     }
 
     pub fn auto_factorial(&self) -> AutoNumResult {
@@ -95,12 +451,29 @@ impl AutoNum {
                         error: String::from("Cannot take the factorial of a negative number"),
                     })
                 } else {
-                    let product = (1..=n)
-                        .map(|n| AutoNum::Int(n))
-                        .fold(AutoNum::Int(1), |acc, x| acc.auto_mul(&x));
-                    Ok(product)
+                    let mut product = BigInt::from_i128(1);
+                    for i in 2..=n {
+                        product = product.mul(&BigInt::from_i128(i as i128));
+                        if product.digit_count() > MAX_DIGITS {
+                            return Err(factorial_too_large());
+                        }
+                    }
+                    Ok(AutoNum::from_big_int(product))
                 }
             }
+            AutoNum::Big(n) if n.is_negative() => Err(EvalError {
+                error: String::from("Cannot take the factorial of a negative number"),
+            }),
+            AutoNum::Big(_) => Err(factorial_too_large()),
+            AutoNum::Complex(..) => Err(EvalError {
+                error: String::from("Cannot take the factorial of a complex number"),
+            }),
+            AutoNum::Interval(..) => Err(EvalError {
+                error: String::from("Cannot take the factorial of an interval"),
+            }),
+            AutoNum::Rational(..) => Err(EvalError {
+                error: String::from("Cannot take the factorial of a non-integer"),
+            }),
             AutoNum::Float(_) => Err(EvalError {
                 error: String::from("Cannot take the factorial of a floating point value"),
             }),
@@ -123,7 +496,26 @@ impl AutoNum {
         }
     }
 
+    pub fn auto_in_domain(
+        &self,
+        f: fn(&f64) -> f64,
+        in_domain: fn(&f64) -> bool,
+        error_msg: &str,
+    ) -> AutoNumResult {
+        let val = self.cast();
+        if in_domain(&val) {
+            Ok(AutoNum::Float(f(&val)))
+        } else {
+            Err(EvalError {
+                error: String::from(error_msg),
+            })
+        }
+    }
+
     pub fn auto_root_n(&self, n: i8) -> AutoNumResult {
+        if let &AutoNum::Complex(re, im) = self {
+            return Ok(AutoNum::from_complex(Complex::new(re, im).root_n(n)));
+        }
         match n {
             2 => self.auto_positive_only(
                 |x| x.sqrt(),
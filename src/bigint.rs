@@ -0,0 +1,344 @@
+use std::{cmp::Ordering, fmt};
+
+const BASE: u64 = 1_000_000_000;
+const BASE_DIGITS: usize = 9;
+
+// Arbitrary-precision integer stored as base 10^9 limbs, least significant limb first.
+// Zero has no limbs and is never negative
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+fn cmp_limbs(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_limbs(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = carry + *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64;
+        result.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// a must not be smaller than b
+fn sub_limbs(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &x) in a.iter().enumerate() {
+        let subtrahend = *b.get(i).unwrap_or(&0) as u64 + borrow;
+        if (x as u64) < subtrahend {
+            result.push((x as u64 + BASE - subtrahend) as u32);
+            borrow = 1;
+        } else {
+            result.push((x as u64 - subtrahend) as u32);
+            borrow = 0;
+        }
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_limbs(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u64; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let current = result[i + j] + x as u64 * y as u64 + carry;
+            result[i + j] = current % BASE;
+            carry = current / BASE;
+        }
+        result[i + b.len()] = carry;
+    }
+    let mut result = result.into_iter().map(|x| x as u32).collect();
+    trim(&mut result);
+    result
+}
+
+// Schoolbook long division, finding each quotient limb with a binary search
+fn divrem_limbs(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut quotient = vec![0; a.len()];
+    let mut remainder = Vec::new();
+    for i in (0..a.len()).rev() {
+        remainder.insert(0, a[i]);
+        trim(&mut remainder);
+
+        let (mut lo, mut hi) = (0, BASE as u32 - 1);
+        while lo < hi {
+            let mid = lo + (hi - lo).div_ceil(2);
+            if cmp_limbs(&mul_limbs(b, &[mid]), &remainder) == Ordering::Greater {
+                hi = mid - 1;
+            } else {
+                lo = mid;
+            }
+        }
+        if lo > 0 {
+            remainder = sub_limbs(&remainder, &mul_limbs(b, &[lo]));
+        }
+        quotient[i] = lo;
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+impl BigInt {
+    fn from_limbs(negative: bool, limbs: Vec<u32>) -> BigInt {
+        BigInt {
+            negative: negative && !limbs.is_empty(),
+            limbs,
+        }
+    }
+
+    pub fn from_i128(n: i128) -> BigInt {
+        let mut limbs = Vec::new();
+        let mut magnitude = n.unsigned_abs();
+        while magnitude > 0 {
+            limbs.push((magnitude % BASE as u128) as u32);
+            magnitude /= BASE as u128;
+        }
+        BigInt::from_limbs(n < 0, limbs)
+    }
+
+    // Parses digits in the given radix, giving None if any digit is invalid
+    pub fn from_str_radix(digits: &str, radix: u32) -> Option<BigInt> {
+        let mut limbs: Vec<u32> = Vec::new();
+        for c in digits.chars() {
+            let mut carry = c.to_digit(radix)? as u64;
+            for limb in limbs.iter_mut() {
+                let current = *limb as u64 * radix as u64 + carry;
+                *limb = (current % BASE) as u32;
+                carry = current / BASE;
+            }
+            if carry > 0 {
+                limbs.push(carry as u32);
+            }
+        }
+        trim(&mut limbs);
+        Some(BigInt::from_limbs(false, limbs))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let magnitude = self.limbs.iter().rev().try_fold(0i128, |acc, &limb| {
+            acc.checked_mul(BASE as i128)?.checked_add(limb as i128)
+        })?;
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    // Parsing the decimal digits gives a correctly rounded result, or infinity if too large
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn digit_count(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => (self.limbs.len() - 1) * BASE_DIGITS + top.to_string().len(),
+            None => 1,
+        }
+    }
+
+    // The decimal digits of the absolute value
+    pub fn magnitude_digits(&self) -> String {
+        let mut digits = self.to_string();
+        if self.negative {
+            digits.remove(0);
+        }
+        digits
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::from_limbs(!self.negative, self.limbs.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_limbs(self.negative, add_limbs(&self.limbs, &other.limbs));
+        }
+        match cmp_limbs(&self.limbs, &other.limbs) {
+            Ordering::Less => {
+                BigInt::from_limbs(other.negative, sub_limbs(&other.limbs, &self.limbs))
+            }
+            _ => BigInt::from_limbs(self.negative, sub_limbs(&self.limbs, &other.limbs)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        BigInt::from_limbs(
+            self.negative != other.negative,
+            mul_limbs(&self.limbs, &other.limbs),
+        )
+    }
+
+    // Truncating division, so the remainder has the same sign as self
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = divrem_limbs(&self.limbs, &other.limbs);
+        Some((
+            BigInt::from_limbs(self.negative != other.negative, quotient),
+            BigInt::from_limbs(self.negative, remainder),
+        ))
+    }
+
+    // Divides the absolute value by a small divisor, giving the quotient and remainder
+    pub fn div_rem_small(&self, divisor: u32) -> (BigInt, u32) {
+        let mut quotient = vec![0; self.limbs.len()];
+        let mut remainder = 0;
+        for (i, &limb) in self.limbs.iter().enumerate().rev() {
+            let current = remainder * BASE + limb as u64;
+            quotient[i] = (current / divisor as u64) as u32;
+            remainder = current % divisor as u64;
+        }
+        trim(&mut quotient);
+        (BigInt::from_limbs(false, quotient), remainder as u32)
+    }
+
+    // Division rounding towards negative infinity, so the remainder has the same sign as other
+    pub fn div_rem_floor(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        let (quotient, remainder) = self.div_rem(other)?;
+        if !remainder.is_zero() && remainder.negative != other.negative {
+            Some((quotient.sub(&BigInt::from_i128(1)), remainder.add(other)))
+        } else {
+            Some((quotient, remainder))
+        }
+    }
+
+    // The absolute value in base 2^32 words, least significant word first
+    fn magnitude_words(&self) -> Vec<u32> {
+        let mut words = Vec::new();
+        let mut rest = BigInt::from_limbs(false, self.limbs.clone());
+        while !rest.is_zero() {
+            let (high, low) = rest.div_rem_small(1 << 16);
+            let (higher, middle) = high.div_rem_small(1 << 16);
+            words.push(middle << 16 | low);
+            rest = higher;
+        }
+        words
+    }
+
+    fn from_magnitude_words(words: &[u32]) -> BigInt {
+        let word_base = BigInt::from_i128(1 << 32);
+        words.iter().rev().fold(BigInt::from_i128(0), |acc, &word| {
+            acc.mul(&word_base).add(&BigInt::from_i128(word as i128))
+        })
+    }
+
+    // Two's complement words, which continue with 1 bits forever for negative numbers
+    fn twos_complement_words(&self) -> Vec<u32> {
+        if self.negative {
+            let words = self.neg().sub(&BigInt::from_i128(1)).magnitude_words();
+            words.iter().map(|word| !word).collect()
+        } else {
+            self.magnitude_words()
+        }
+    }
+
+    // Applies op to the bits of the two's complement representations, where negative
+    // numbers have infinitely many leading 1 bits
+    pub fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let (a, b) = (self.twos_complement_words(), other.twos_complement_words());
+        let sign_word = |negative: bool| if negative { u32::MAX } else { 0 };
+        let word = |words: &[u32], negative: bool, i: usize| {
+            words.get(i).copied().unwrap_or(sign_word(negative))
+        };
+        let words: Vec<u32> = (0..a.len().max(b.len()))
+            .map(|i| op(word(&a, self.negative, i), word(&b, other.negative, i)))
+            .collect();
+
+        if op(sign_word(self.negative), sign_word(other.negative)) == 0 {
+            return BigInt::from_magnitude_words(&words);
+        }
+        let inverted: Vec<u32> = words.iter().map(|word| !word).collect();
+        BigInt::from_magnitude_words(&inverted)
+            .add(&BigInt::from_i128(1))
+            .neg()
+    }
+
+    pub fn not(&self) -> BigInt {
+        self.neg().sub(&BigInt::from_i128(1))
+    }
+
+    // Arithmetic shift, rounding towards negative infinity
+    pub fn shr(&self, shift: u64) -> BigInt {
+        // each limb is less than 2^30
+        if shift >= self.limbs.len() as u64 * 30 {
+            return BigInt::from_i128(if self.negative { -1 } else { 0 });
+        }
+        let divisor = BigInt::from_i128(2).pow(shift as u32);
+        match self.div_rem_floor(&divisor) {
+            Some((quotient, _)) => quotient,
+            None => unreachable!(),
+        }
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut m, mut n) = (
+            BigInt::from_limbs(false, self.limbs.clone()),
+            BigInt::from_limbs(false, other.limbs.clone()),
+        );
+        while let Some((_, remainder)) = m.div_rem(&n) {
+            m = n;
+            n = remainder;
+        }
+        m
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::from_i128(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.mul(&base);
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((top, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{top}")?;
+        for limb in rest.iter().rev() {
+            write!(f, "{limb:09}")?;
+        }
+        Ok(())
+    }
+}
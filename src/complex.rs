@@ -0,0 +1,161 @@
+use std::f64::consts::{FRAC_PI_2, LN_10};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+const I: Complex = Complex { re: 0.0, im: 1.0 };
+const ONE: Complex = Complex { re: 1.0, im: 0.0 };
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        Complex::new(r * theta.cos(), r * theta.sin())
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+
+    pub fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+
+    pub fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+
+    pub fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+
+    pub fn div(self, other: Complex) -> Complex {
+        let denom = other.re * other.re + other.im * other.im;
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denom,
+            (self.im * other.re - self.re * other.im) / denom,
+        )
+    }
+
+    pub fn exp(self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    pub fn ln(self) -> Complex {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    pub fn log10(self) -> Complex {
+        let ln = self.ln();
+        Complex::new(ln.re / LN_10, ln.im / LN_10)
+    }
+
+    pub fn pow(self, exponent: Complex) -> Complex {
+        if self.re == 0.0 && self.im == 0.0 {
+            return Complex::new(if exponent.re > 0.0 { 0.0 } else { f64::NAN }, 0.0);
+        }
+        exponent.mul(self.ln()).exp()
+    }
+
+    // Avoids going through polar form so that square roots of negative reals are exact
+    pub fn sqrt(self) -> Complex {
+        let t = ((self.re.abs() + self.abs()) / 2.0).sqrt();
+        if t == 0.0 {
+            Complex::new(0.0, 0.0)
+        } else if self.re >= 0.0 {
+            Complex::new(t, self.im / (2.0 * t))
+        } else {
+            Complex::new(self.im.abs() / (2.0 * t), t.copysign(self.im))
+        }
+    }
+
+    pub fn root_n(self, n: i8) -> Complex {
+        match n {
+            2 => self.sqrt(),
+            _ => Complex::from_polar(self.abs().powf(1.0 / n as f64), self.arg() / n as f64),
+        }
+    }
+
+    pub fn sin(self) -> Complex {
+        Complex::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos(self) -> Complex {
+        Complex::new(
+            self.re.cos() * self.im.cosh(),
+            -self.re.sin() * self.im.sinh(),
+        )
+    }
+
+    pub fn tan(self) -> Complex {
+        self.sin().div(self.cos())
+    }
+
+    pub fn sinh(self) -> Complex {
+        Complex::new(
+            self.re.sinh() * self.im.cos(),
+            self.re.cosh() * self.im.sin(),
+        )
+    }
+
+    pub fn cosh(self) -> Complex {
+        Complex::new(
+            self.re.cosh() * self.im.cos(),
+            self.re.sinh() * self.im.sin(),
+        )
+    }
+
+    pub fn tanh(self) -> Complex {
+        self.sinh().div(self.cosh())
+    }
+
+    // asin(z) = -i ln(iz + sqrt(1 - z^2))
+    pub fn asin(self) -> Complex {
+        let root = ONE.sub(self.mul(self)).sqrt();
+        I.mul(self).add(root).ln().mul(I).neg()
+    }
+
+    pub fn acos(self) -> Complex {
+        Complex::new(FRAC_PI_2, 0.0).sub(self.asin())
+    }
+
+    // atan(z) = i/2 (ln(1 - iz) - ln(1 + iz))
+    pub fn atan(self) -> Complex {
+        let iz = I.mul(self);
+        let diff = ONE.sub(iz).ln().sub(ONE.add(iz).ln());
+        Complex::new(-diff.im / 2.0, diff.re / 2.0)
+    }
+
+    pub fn asinh(self) -> Complex {
+        self.add(self.mul(self).add(ONE).sqrt()).ln()
+    }
+
+    pub fn acosh(self) -> Complex {
+        self.add(self.add(ONE).sqrt().mul(self.sub(ONE).sqrt()))
+            .ln()
+    }
+
+    pub fn atanh(self) -> Complex {
+        let diff = ONE.add(self).ln().sub(ONE.sub(self).ln());
+        Complex::new(diff.re / 2.0, diff.im / 2.0)
+    }
+}
@@ -0,0 +1,35 @@
+use std::{env, fs, path::PathBuf};
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("caulc"));
+    }
+    if let Some(dir) = env::var_os("APPDATA").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("caulc"));
+    }
+    env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|home| PathBuf::from(home).join(".config").join("caulc"))
+}
+
+pub fn history_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("history"))
+}
+
+pub fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config"))
+}
+
+// Settings are 'key = value' lines, and blank lines or lines starting with '#' are skipped
+pub fn load_settings() -> Vec<(String, String)> {
+    let Some(contents) = settings_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (String::from(key.trim()), String::from(value.trim())))
+        .collect()
+}
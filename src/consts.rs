@@ -8,22 +8,48 @@ use crate::{
 pub struct Definitions<'a> {
     pub constants: HashMap<&'a str, Quantity>,
     units: HashMap<&'a str, Quantity>,
+    affine_units: HashMap<&'a str, (Quantity, AutoNum)>,
 }
 
+// Named SI derived units that results are displayed in when their dimensions match
+const DERIVED_UNIT_NAMES: [&str; 12] = [
+    "J", "N", "W", "Pa", "V", "ohm", "F", "H", "T", "Wb", "C", "Hz",
+];
+
+// Units that autoprefix may add a metric prefix to, the SI base and named derived units
+// along with the litre
+const PREFIXABLE_UNIT_NAMES: [&str; 8] = ["g", "m", "s", "A", "K", "mol", "cd", "L"];
+
 impl<'a> Definitions<'a> {
     pub fn get_default() -> Definitions<'a> {
         Definitions {
             constants: get_default_constants(),
             units: get_default_units(),
+            affine_units: get_default_affine_units(),
         }
     }
 
+    pub fn is_prefixable_unit(&self, unit: &str) -> bool {
+        PREFIXABLE_UNIT_NAMES.contains(&unit) || DERIVED_UNIT_NAMES.contains(&unit)
+    }
+
+    pub fn get_derived_units(&self) -> Vec<(&'static str, Dimension)> {
+        DERIVED_UNIT_NAMES
+            .iter()
+            .filter_map(|&name| Some((name, self.units.get(name)?.units.clone())))
+            .collect()
+    }
+
+    pub fn get_affine_unit(&self, unit: &str) -> Option<(Quantity, AutoNum)> {
+        self.affine_units.get(unit).cloned()
+    }
+
     pub fn get_unit(&self, unit: &str) -> Option<Quantity> {
         if let Some(u) = self.units.get(unit) {
             return Some(u.clone());
         }
 
-        let prefix_factor = get_metric_prefix(unit.chars().nth(0)?)?;
+        let prefix_factor = get_metric_prefix(unit.chars().next()?)?;
         let base_unit = &unit[1..];
         let unit_quantity = self.units.get(base_unit)?;
         Some(Quantity::new(
@@ -44,10 +70,27 @@ fn get_metric_prefix(c: char) -> Option<f64> {
         'm' => Some(1e-3),
         'u' => Some(1e-6),
         'n' => Some(1e-9),
+        'p' => Some(1e-12),
         _ => None,
     }
 }
 
+const ENGINEERING_PREFIXES: [char; 8] = ['T', 'G', 'M', 'k', 'm', 'u', 'n', 'p'];
+
+// Picks the metric prefix which puts the magnitude of x in [1, 1000), if x isn't already.
+// Values outside the range of the prefixes are left without one
+pub fn get_engineering_prefix(x: f64) -> Option<(char, f64)> {
+    let magnitude = x.abs();
+    if !magnitude.is_normal() || (1.0..1000.0).contains(&magnitude) {
+        return None;
+    }
+
+    ENGINEERING_PREFIXES
+        .iter()
+        .filter_map(|&c| Some((c, get_metric_prefix(c)?)))
+        .find(|&(_, factor)| (factor..factor * 1000.0).contains(&magnitude))
+}
+
 fn qconst(value: f64, units: Dimension) -> Quantity {
     Quantity::new(AutoNum::Float(value), units)
 }
@@ -63,6 +106,7 @@ fn get_default_constants<'a>() -> HashMap<&'a str, Quantity> {
     result.insert("tau", uconst(std::f64::consts::TAU));
     result.insert("e", uconst(std::f64::consts::E));
     result.insert("golden", uconst(1.61803398875));
+    result.insert("i", Quantity::dimensionless(AutoNum::Complex(0.0, 1.0)));
     result.insert(
         "c",
         qconst(299792458.0, Dimension::new(0, 1, -1, 0, 0, 0, 0, 1)),
@@ -165,12 +209,38 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
         "ton",
         qconst(1000.0, Dimension::new(1, 0, 0, 0, 0, 0, 0, 1)),
     );
+    result.insert(
+        "lb",
+        qconst(0.45359237, Dimension::new(1, 0, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "oz",
+        qconst(0.028349523125, Dimension::new(1, 0, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "ton_us",
+        qconst(907.18474, Dimension::new(1, 0, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "ton_imp",
+        qconst(1016.0469088, Dimension::new(1, 0, 0, 0, 0, 0, 0, 1)),
+    );
     // length
     result.insert(
         "ansgtrom",
         qconst(1e-10, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)),
     );
+    // customary units use the exact international definitions from NIST Handbook 44
+    result.insert(
+        "inch",
+        qconst(0.0254, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)),
+    );
     result.insert("ft", qconst(0.3048, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)));
+    result.insert("yd", qconst(0.9144, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)));
+    result.insert(
+        "mi",
+        qconst(1609.344, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)),
+    );
     result.insert("NM", qconst(1852.0, Dimension::new(0, 1, 0, 0, 0, 0, 0, 1)));
     result.insert(
         "AU",
@@ -186,6 +256,48 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
     );
     // volume
     result.insert("L", qconst(0.001, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)));
+    result.insert(
+        "gal",
+        qconst(3.785411784e-3, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "qt",
+        qconst(9.46352946e-4, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "pt",
+        qconst(4.73176473e-4, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "fl_oz",
+        qconst(2.95735295625e-5, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "gal_imp",
+        qconst(4.54609e-3, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "qt_imp",
+        qconst(1.1365225e-3, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "pt_imp",
+        qconst(5.6826125e-4, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    result.insert(
+        "fl_oz_imp",
+        qconst(2.84130625e-5, Dimension::new(0, 3, 0, 0, 0, 0, 0, 1)),
+    );
+    // area
+    result.insert(
+        "acre",
+        qconst(4046.8564224, Dimension::new(0, 2, 0, 0, 0, 0, 0, 1)),
+    );
+    // speed
+    result.insert(
+        "mph",
+        qconst(0.44704, Dimension::new(0, 1, -1, 0, 0, 0, 0, 1)),
+    );
     // time
     result.insert("min", qconst(60.0, Dimension::new(0, 0, 1, 0, 0, 0, 0, 1)));
     result.insert("h", qconst(3600.0, Dimension::new(0, 0, 1, 0, 0, 0, 0, 1)));
@@ -199,6 +311,10 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
         "gf",
         qconst(0.009807, Dimension::new(1, 1, -2, 0, 0, 0, 0, 1)),
     );
+    result.insert(
+        "lbf",
+        qconst(4.4482216152605, Dimension::new(1, 1, -2, 0, 0, 0, 0, 1)),
+    );
     // energy
     result.insert(
         "eV",
@@ -212,6 +328,15 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
         "Cal",
         qconst(4184.0, Dimension::new(1, 2, -2, 0, 0, 0, 0, 1)),
     );
+    result.insert(
+        "BTU",
+        qconst(1055.05585262, Dimension::new(1, 2, -2, 0, 0, 0, 0, 1)),
+    );
+    // power
+    result.insert(
+        "hp",
+        qconst(745.6998715822702, Dimension::new(1, 2, -3, 0, 0, 0, 0, 1)),
+    );
     // pressure
     result.insert("bar", qconst(1e5, Dimension::new(1, -1, -2, 0, 0, 0, 0, 1)));
     result.insert(
@@ -222,6 +347,15 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
         "mHg",
         qconst(133322.0, Dimension::new(1, -1, -2, 0, 0, 0, 0, 1)),
     );
+    result.insert(
+        "psi",
+        qconst(6894.757293168361, Dimension::new(1, -1, -2, 0, 0, 0, 0, 1)),
+    );
+    // temperature
+    result.insert(
+        "degR",
+        qconst(5.0 / 9.0, Dimension::new(0, 0, 0, 0, 1, 0, 0, 1)),
+    );
     // Other units
     result.insert("deg", uconst(std::f64::consts::PI / 180.0));
     result.insert("am", uconst(std::f64::consts::PI / 180.0 / 60.0));
@@ -230,3 +364,104 @@ fn get_default_units<'a>() -> HashMap<&'a str, Quantity> {
 
     result
 }
+
+// Units which are scaled then offset from absolute zero, as (scale, offset in K)
+fn get_default_affine_units<'a>() -> HashMap<&'a str, (Quantity, AutoNum)> {
+    let mut result = HashMap::new();
+
+    result.insert(
+        "degC",
+        (
+            qconst(1.0, Dimension::new(0, 0, 0, 0, 1, 0, 0, 1)),
+            AutoNum::Float(273.15),
+        ),
+    );
+    result.insert(
+        "degF",
+        (
+            qconst(5.0 / 9.0, Dimension::new(0, 0, 0, 0, 1, 0, 0, 1)),
+            AutoNum::Float(459.67 * 5.0 / 9.0),
+        ),
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exact conversion factors to SI from NIST SP 811 Appendix B and NIST Handbook 44
+    fn assert_nist_factor(unit: &str, expected: f64, units: Dimension) {
+        let quantity = Definitions::get_default().get_unit(unit).unwrap();
+        let value = quantity.value.cast();
+        assert!(
+            ((value - expected) / expected).abs() < 1e-15,
+            "{unit} is {value}, expected {expected}"
+        );
+        assert_eq!(quantity.units, units, "{unit} has the wrong dimensions");
+    }
+
+    #[test]
+    fn length_factors_match_nist() {
+        let length = Dimension::new(0, 1, 0, 0, 0, 0, 0, 1);
+        assert_nist_factor("inch", 2.54e-2, length.clone());
+        assert_nist_factor("yd", 9.144e-1, length.clone());
+        assert_nist_factor("mi", 1.609344e3, length);
+    }
+
+    #[test]
+    fn mass_factors_match_nist() {
+        let mass = Dimension::new(1, 0, 0, 0, 0, 0, 0, 1);
+        assert_nist_factor("lb", 4.5359237e-1, mass.clone());
+        assert_nist_factor("oz", 2.8349523125e-2, mass.clone());
+        assert_nist_factor("ton_us", 9.0718474e2, mass.clone());
+        assert_nist_factor("ton_imp", 1.0160469088e3, mass);
+    }
+
+    #[test]
+    fn volume_factors_match_nist() {
+        let volume = Dimension::new(0, 3, 0, 0, 0, 0, 0, 1);
+        assert_nist_factor("gal", 3.785411784e-3, volume.clone());
+        assert_nist_factor("qt", 9.46352946e-4, volume.clone());
+        assert_nist_factor("pt", 4.73176473e-4, volume.clone());
+        assert_nist_factor("fl_oz", 2.95735295625e-5, volume.clone());
+        assert_nist_factor("gal_imp", 4.54609e-3, volume.clone());
+        assert_nist_factor("qt_imp", 1.1365225e-3, volume.clone());
+        assert_nist_factor("pt_imp", 5.6826125e-4, volume.clone());
+        assert_nist_factor("fl_oz_imp", 2.84130625e-5, volume);
+    }
+
+    #[test]
+    fn area_and_speed_factors_match_nist() {
+        // the acre is 43560 square international feet, as the survey foot is no longer used
+        assert_nist_factor(
+            "acre",
+            4.0468564224e3,
+            Dimension::new(0, 2, 0, 0, 0, 0, 0, 1),
+        );
+        assert_nist_factor("mph", 4.4704e-1, Dimension::new(0, 1, -1, 0, 0, 0, 0, 1));
+    }
+
+    #[test]
+    fn mechanical_factors_match_nist() {
+        // psi and hp are derived from lbf, which is the exact lb times standard gravity
+        let lbf = 4.5359237e-1 * 9.80665;
+        assert_nist_factor("lbf", lbf, Dimension::new(1, 1, -2, 0, 0, 0, 0, 1));
+        assert_nist_factor(
+            "psi",
+            lbf / (2.54e-2 * 2.54e-2),
+            Dimension::new(1, -1, -2, 0, 0, 0, 0, 1),
+        );
+        assert_nist_factor(
+            "hp",
+            550.0 * 3.048e-1 * lbf,
+            Dimension::new(1, 2, -3, 0, 0, 0, 0, 1),
+        );
+        assert_nist_factor(
+            "BTU",
+            1.05505585262e3,
+            Dimension::new(1, 2, -2, 0, 0, 0, 0, 1),
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{Closure, Function},
+    config,
+    query::GroupDisplay,
+    units::Quantity,
+};
+
+#[derive(Default, Clone)]
+pub struct Environment {
+    variables: HashMap<String, Quantity>,
+    functions: Rc<HashMap<String, Rc<Closure>>>,
+    complex: bool,
+    group: Option<GroupDisplay>,
+    call_depth: usize,
+}
+
+impl Environment {
+    // The default environment with the settings from the config file applied
+    pub fn load() -> Environment {
+        let mut env = Environment::default();
+        for (key, value) in config::load_settings() {
+            match key.as_str() {
+                "group" => match GroupDisplay::from_name(&value) {
+                    Some(group) => env.group = Some(group),
+                    None => eprintln!(
+                        "Unknown digit grouping '{value}' in config, expected comma/space/european/none"
+                    ),
+                },
+                _ => eprintln!("Unknown setting '{key}' in config"),
+            }
+        }
+        env
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&Quantity> {
+        self.variables.get(name)
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: Quantity) {
+        self.variables.insert(String::from(name), value);
+    }
+
+    pub fn variable_names(&self) -> impl Iterator<Item = &String> {
+        self.variables.keys()
+    }
+
+    // The scope of a function call, which shares the functions and settings but none of the
+    // caller's variables
+    pub fn with_variables(&self, variables: HashMap<String, Quantity>) -> Environment {
+        Environment {
+            variables,
+            functions: Rc::clone(&self.functions),
+            complex: self.complex,
+            group: self.group,
+            call_depth: self.call_depth + 1,
+        }
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&Rc<Closure>> {
+        self.functions.get(name)
+    }
+
+    pub fn set_function(&mut self, name: &str, function: Rc<Function>) {
+        let closure = Rc::new(Closure::new(function, self));
+        Rc::make_mut(&mut self.functions).insert(String::from(name), closure);
+    }
+
+    pub fn is_complex(&self) -> bool {
+        self.complex
+    }
+
+    pub fn set_complex(&mut self, complex: bool) {
+        self.complex = complex;
+    }
+
+    pub fn default_group(&self) -> Option<GroupDisplay> {
+        self.group
+    }
+
+    pub fn function_arities(&self) -> impl Iterator<Item = (&String, usize)> {
+        self.functions
+            .iter()
+            .map(|(name, closure)| (name, closure.function.params.len()))
+    }
+}
@@ -27,29 +27,37 @@ impl<'a> ParseError<'a> {
         }
     }
 
-    pub fn display_error_to_stderr(&self) {
-        eprintln!("\x1B[mError in parsing: {}", self.error);
-        eprintln!(" | {}", self.original);
+    fn display_carets(&self, prefix: &str, offset: usize) {
         let token_len = (self.end_pos - self.start_pos).max(1);
-        if self.start_pos == 0 {
-            eprintln!(" | \x1B[31m{}", "^".repeat(token_len));
+        let column = offset + self.start_pos;
+        if column == 0 {
+            eprintln!("{prefix}\x1B[31m{}", "^".repeat(token_len));
         } else {
-            eprintln!(
-                " | \x1B[{}C\x1B[31m{}",
-                self.start_pos,
-                "^".repeat(token_len)
-            );
+            eprintln!("{prefix}\x1B[{column}C\x1B[31m{}", "^".repeat(token_len));
         }
     }
+
+    pub fn display_error_to_stderr(&self) {
+        eprintln!("\x1B[mError in parsing: {}", self.error);
+        eprintln!(" | {}", self.original);
+        self.display_carets(" | ", 0);
+    }
+
+    pub fn display_error_below_prompt(&self, prompt_len: usize) {
+        self.display_carets("", prompt_len);
+        eprintln!("\x1B[mError in parsing: {}", self.error);
+    }
 }
 
 pub fn get_token_str(token: &Token) -> String {
     match token.data {
         TokenData::Num(_) => format!("number '{}'", token.substr),
-        TokenData::Int(_) => format!("integer '{}'", token.substr),
+        TokenData::Int(_) | TokenData::Big(_) => format!("integer '{}'", token.substr),
+        TokenData::Duration(_) => format!("duration '{}'", token.substr),
         TokenData::LBracket => String::from("left bracket '('"),
         TokenData::RBracket => String::from("right bracket ')'"),
         TokenData::Sym(c) => format!("symbol '{c}'"),
+        TokenData::Op(s) => format!("operator '{s}'"),
         TokenData::Word(s) => format!("word '{s}'"),
         TokenData::EndOfInput => String::from("end of input"),
     }
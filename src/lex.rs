@@ -1,11 +1,14 @@
-use crate::error::ParseError;
+use crate::{bigint::BigInt, error::ParseError};
 use std::{iter::Peekable, str::CharIndices};
 
 #[derive(Clone, Debug)]
 pub enum TokenData<'a> {
     Num(f64),
     Int(i64),
+    Big(BigInt),
+    Duration(f64),
     Sym(char),
+    Op(&'a str),
     Word(&'a str),
     LBracket,
     RBracket,
@@ -20,6 +23,7 @@ pub struct Token<'a> {
     pub substr: &'a str,
 }
 
+#[derive(Clone)]
 pub struct LexIter<'a> {
     original: &'a str,
     chars: Peekable<CharIndices<'a>>,
@@ -75,9 +79,11 @@ impl<'a> LexIter<'a> {
     }
 
     fn try_parse_num(&mut self) -> LexResult<'a> {
-        let substr = self.get_substr();
+        let substr = self.get_substr().replace(['_', '\''], "");
         if let Ok(n) = substr.parse::<i64>() {
             Ok(self.make_token(TokenData::Int(n)))
+        } else if let Some(n) = BigInt::from_str_radix(&substr, 10) {
+            Ok(self.make_token(TokenData::Big(n)))
         } else if let Ok(x) = substr.parse::<f64>() {
             Ok(self.make_token(TokenData::Num(x)))
         } else {
@@ -90,19 +96,86 @@ impl<'a> LexIter<'a> {
         }
     }
 
+    // Steps over digits, which can be grouped with single '_' or '\'' separators between them.
+    // Returns whether any digits were found
+    fn lex_digits(&mut self, radix: u32, after_digit: bool) -> Result<bool, ParseError<'a>> {
+        let mut has_parsed_digits = false;
+        loop {
+            match self.peek_char() {
+                Some(c) if c.is_digit(radix) => {
+                    self.step_char();
+                    has_parsed_digits = true;
+                }
+                Some('_' | '\'') => {
+                    let separator_pos = self.curr_actual_pos;
+                    self.step_char();
+                    let error = if !(after_digit || has_parsed_digits) {
+                        "Digit separator must come after a digit"
+                    } else if matches!(self.peek_char(), Some('_' | '\'')) {
+                        "Repeated digit separator"
+                    } else if !self.peek_char().is_some_and(|c| c.is_digit(radix)) {
+                        "Digit separator must be followed by a digit"
+                    } else {
+                        continue;
+                    };
+                    return Err(ParseError::from_pos(
+                        String::from(error),
+                        separator_pos,
+                        self.original,
+                    ));
+                }
+                _ => return Ok(has_parsed_digits),
+            }
+        }
+    }
+
+    // Integer literals with a 0x, 0b or 0o prefix
+    fn lex_radix_num(&mut self, radix: u32) -> LexResult<'a> {
+        self.step_char();
+        self.lex_digits(radix, false)?;
+        if let Some(c) = self.peek_char().filter(|c| c.is_ascii_alphanumeric()) {
+            return Err(ParseError::from_pos(
+                format!("Invalid digit '{c}' in base {radix} number"),
+                self.curr_actual_pos,
+                self.original,
+            ));
+        }
+
+        let digits = self.get_substr()[2..].replace(['_', '\''], "");
+        match i64::from_str_radix(&digits, radix) {
+            Ok(n) => Ok(self.make_token(TokenData::Int(n))),
+            Err(_) => match BigInt::from_str_radix(&digits, radix) {
+                Some(n) => Ok(self.make_token(TokenData::Big(n))),
+                None => unreachable!(),
+            },
+        }
+    }
+
     fn lex_num(&mut self) -> LexResult<'a> {
         let mut has_parsed_digits = false;
-        while self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+        if self.peek_char() == Some('0') {
             self.step_char();
             has_parsed_digits = true;
+            // Only a prefix when a digit follows, so that units such as 0ohm and 0bar still work
+            let radix = match self.peek_char() {
+                Some('x' | 'X') => 16,
+                Some('b' | 'B') => 2,
+                Some('o' | 'O') => 8,
+                _ => 0,
+            };
+            let mut lookahead = self.chars.clone().map(|(_, c)| c).skip(1);
+            if radix != 0 && lookahead.next().is_some_and(|c| c.is_digit(radix)) {
+                return self.lex_radix_num(radix);
+            }
         }
 
+        has_parsed_digits |= self.lex_digits(10, has_parsed_digits)?;
+        if has_parsed_digits && self.duration_follows() {
+            return self.lex_duration();
+        }
         if self.peek_char().is_some_and(|c| c == '.') {
             self.step_char();
-            while self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
-                self.step_char();
-                has_parsed_digits = true;
-            }
+            has_parsed_digits |= self.lex_digits(10, false)?;
         }
         if !has_parsed_digits {
             return Err(ParseError::from_pos(
@@ -124,19 +197,67 @@ impl<'a> LexIter<'a> {
                     self.original,
                 ));
             }
+            self.lex_digits(10, false)?;
+        }
+
+        self.try_parse_num()
+    }
+
+    // A ':' straight after digits and followed by two more starts a duration rather than
+    // the undim prefix, which only appears at the start of an expression
+    fn duration_follows(&self) -> bool {
+        let mut chars = self.chars.clone().map(|(_, c)| c);
+        chars.next() == Some(':')
+            && chars.next().is_some_and(|c| c.is_ascii_digit())
+            && chars.next().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    // Durations written as h:mm or h:mm:ss, with an optional fraction of a second
+    fn lex_duration(&mut self) -> LexResult<'a> {
+        let hours = self.get_substr().replace(['_', '\''], "");
+        let mut seconds = hours.parse::<f64>().unwrap_or(f64::INFINITY) * 3600.0;
+        let mut field_scale = 60.0;
+        while field_scale >= 1.0 && self.duration_follows() {
             self.step_char();
-            while self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+            let field_start_pos = self.curr_actual_pos;
+            let mut field = 0;
+            for _ in 0..2 {
+                field = field * 10 + self.peek_char().and_then(|c| c.to_digit(10)).unwrap_or(0);
                 self.step_char();
             }
+            if self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(ParseError::from_pos(
+                    String::from("Expected exactly two digits after ':' in a duration"),
+                    self.curr_actual_pos,
+                    self.original,
+                ));
+            }
+            if field >= 60 {
+                return Err(ParseError {
+                    error: String::from("Minutes and seconds in a duration must be less than 60"),
+                    start_pos: field_start_pos,
+                    end_pos: self.curr_actual_pos,
+                    original: self.original,
+                });
+            }
+            seconds += field as f64 * field_scale;
+            field_scale /= 60.0;
         }
 
-        self.try_parse_num()
+        if field_scale < 1.0 && self.peek_char() == Some('.') {
+            self.step_char();
+            self.lex_digits(10, false)?;
+            let substr = self.get_substr();
+            let fraction = substr[substr.rfind('.').unwrap_or(0)..].replace(['_', '\''], "");
+            seconds += fraction.parse::<f64>().unwrap_or(0.0);
+        }
+        Ok(self.make_token(TokenData::Duration(seconds)))
     }
 
     fn lex_word(&mut self) -> Token<'a> {
         while self
             .peek_char()
-            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
         {
             self.step_char();
         }
@@ -158,6 +279,29 @@ impl<'a> LexIter<'a> {
         match curr {
             '(' => Ok(self.make_token(TokenData::LBracket)),
             ')' => Ok(self.make_token(TokenData::RBracket)),
+            // +/- is an ASCII spelling of ±, and unlike +- it cannot be part of an expression
+            '+' if self.chars.clone().map(|(_, c)| c).take(2).eq(['/', '-']) => {
+                self.step_char();
+                self.step_char();
+                Ok(self.make_token(TokenData::Sym('±')))
+            }
+            // +- could mean either ± or adding a negative number, so neither is guessed
+            '+' if self.peek_char() == Some('-') => {
+                self.step_char();
+                Err(ParseError {
+                    error: String::from(
+                        "ambiguous '+-', write '±' or '+/-' for an uncertainty, or '+ -' to add a negative",
+                    ),
+                    start_pos: self.token_start_pos,
+                    end_pos: self.curr_actual_pos,
+                    original: self.original,
+                })
+            }
+            '<' | '>' | '/' if self.peek_char() == Some(curr) => {
+                self.step_char();
+                let op = self.get_substr();
+                Ok(self.make_token(TokenData::Op(op)))
+            }
             _ => Ok(self.make_token(TokenData::Sym(curr))),
         }
     }
@@ -179,6 +323,7 @@ impl<'a> Iterator for LexIter<'a> {
     }
 }
 
+#[derive(Clone)]
 pub struct Lexer<'a> {
     lex_iter: Peekable<LexIter<'a>>,
     last_token_end_pos: usize,
@@ -220,4 +365,13 @@ impl<'a> Lexer<'a> {
             None => Ok(self.make_eof_token()),
         }
     }
+
+    pub fn peek_second_token(&mut self) -> LexResult<'a> {
+        let mut lookahead = self.lex_iter.clone();
+        let _ = lookahead.next();
+        match lookahead.next() {
+            Some(token) => token,
+            None => Ok(self.make_eof_token()),
+        }
+    }
 }
@@ -2,18 +2,25 @@ use std::process::ExitCode;
 
 mod ast;
 mod autonum;
+mod bigint;
+mod complex;
+mod config;
 mod consts;
+mod env;
 mod error;
 mod lex;
 mod operator;
 mod parse;
 mod query;
+mod repl;
 mod units;
 
-fn display_evaluation(query: &query::Query) -> ExitCode {
-    match query.get_answer() {
+fn display_evaluation(query: &query::Query, env: &mut env::Environment) -> ExitCode {
+    match query.get_answer(env) {
         Ok(answer) => {
-            println!("{answer}");
+            if let Some(answer) = answer {
+                println!("{answer}");
+            }
             ExitCode::SUCCESS
         }
         Err(e) => {
@@ -26,13 +33,14 @@ fn display_evaluation(query: &query::Query) -> ExitCode {
 fn main() -> ExitCode {
     let q = std::env::args().skip(1).collect::<Vec<String>>().join(" ");
 
+    let defs = consts::Definitions::get_default();
     if q.is_empty() {
-        eprintln!("Provide an expression");
-        return ExitCode::FAILURE;
+        return repl::run(&defs);
     }
 
-    match query::parse(q.as_str(), &consts::Definitions::get_default()) {
-        Ok(query) => display_evaluation(&query),
+    let mut env = env::Environment::load();
+    match query::parse(q.as_str(), &defs, &env) {
+        Ok(query) => display_evaluation(&query, &mut env),
         Err(e) => {
             e.display_error_to_stderr();
             ExitCode::FAILURE
@@ -1,21 +1,64 @@
+use crate::lex::TokenData;
+
 pub enum BinaryOp {
     Add,
     Sub,
     Mul,
     Div,
     Pow,
+    PlusMinus,
+    IntDiv,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Pow => "^",
+            BinaryOp::PlusMinus => "±",
+            BinaryOp::IntDiv => "//",
+            BinaryOp::Mod => "mod",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "xor",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+        }
+    }
 }
 
-pub fn try_get_binary_operator(data: char) -> Option<(BinaryOp, u8, bool)> {
+pub fn try_get_binary_operator(data: &TokenData) -> Option<(BinaryOp, u8, bool)> {
     match data {
-        '+' => Some((BinaryOp::Add, 10, false)),
-        '-' => Some((BinaryOp::Sub, 10, false)),
-        '*' => Some((BinaryOp::Mul, 20, false)),
-        '/' => Some((BinaryOp::Div, 20, false)),
+        TokenData::Sym('|') => Some((BinaryOp::BitOr, 4, false)),
+        TokenData::Word("xor") => Some((BinaryOp::BitXor, 5, false)),
+        TokenData::Sym('&') => Some((BinaryOp::BitAnd, 6, false)),
+        TokenData::Op("<<") => Some((BinaryOp::Shl, 8, false)),
+        TokenData::Op(">>") => Some((BinaryOp::Shr, 8, false)),
+        TokenData::Sym('+') => Some((BinaryOp::Add, 10, false)),
+        TokenData::Sym('-') => Some((BinaryOp::Sub, 10, false)),
+        TokenData::Sym('±') => Some((BinaryOp::PlusMinus, 15, false)),
+        TokenData::Sym('*') => Some((BinaryOp::Mul, 20, false)),
+        TokenData::Sym('/') => Some((BinaryOp::Div, 20, false)),
+        TokenData::Op("//") => Some((BinaryOp::IntDiv, 20, false)),
+        TokenData::Word("mod") | TokenData::Sym('%') => Some((BinaryOp::Mod, 20, false)),
         _ => None,
     }
 }
 
+// Words that are binary operators, which end a list of units
+pub fn is_operator_word(w: &str) -> bool {
+    matches!(w, "xor" | "mod")
+}
+
 pub enum UnaryOp {
     Positive,
     Negative,
@@ -25,9 +68,22 @@ pub enum UnaryOp {
     Sin,
     Cos,
     Tan,
+    Sec,
+    Csc,
+    Cot,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asinh,
+    Acosh,
+    Atanh,
     Exp,
     Ln,
     Log,
+    BitNot,
     Undim,
 }
 
@@ -35,6 +91,7 @@ pub fn try_get_prefix_operator(data: char) -> Option<UnaryOp> {
     match data {
         '+' => Some(UnaryOp::Positive),
         '-' => Some(UnaryOp::Negative),
+        '~' => Some(UnaryOp::BitNot),
         _ => None,
     }
 }
@@ -54,9 +111,55 @@ pub fn try_get_function(w: &str) -> Option<UnaryOp> {
         "sin" => Some(UnaryOp::Sin),
         "cos" => Some(UnaryOp::Cos),
         "tan" => Some(UnaryOp::Tan),
+        "sec" => Some(UnaryOp::Sec),
+        "csc" => Some(UnaryOp::Csc),
+        "cot" => Some(UnaryOp::Cot),
+        "asin" => Some(UnaryOp::Asin),
+        "acos" => Some(UnaryOp::Acos),
+        "atan" => Some(UnaryOp::Atan),
+        "sinh" => Some(UnaryOp::Sinh),
+        "cosh" => Some(UnaryOp::Cosh),
+        "tanh" => Some(UnaryOp::Tanh),
+        "asinh" => Some(UnaryOp::Asinh),
+        "acosh" => Some(UnaryOp::Acosh),
+        "atanh" => Some(UnaryOp::Atanh),
         "exp" => Some(UnaryOp::Exp),
         "ln" => Some(UnaryOp::Ln),
         "log" => Some(UnaryOp::Log),
         _ => None,
     }
 }
+
+pub enum FunctionOp {
+    Atan2,
+    Hypot,
+    Min,
+    Max,
+    LogBase,
+    Clamp,
+}
+
+impl FunctionOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FunctionOp::Atan2 => "atan2",
+            FunctionOp::Hypot => "hypot",
+            FunctionOp::Min => "min",
+            FunctionOp::Max => "max",
+            FunctionOp::LogBase => "log",
+            FunctionOp::Clamp => "clamp",
+        }
+    }
+}
+
+pub fn try_get_multi_arg_function(w: &str) -> Option<(FunctionOp, usize, Option<usize>)> {
+    match w {
+        "atan2" => Some((FunctionOp::Atan2, 2, Some(2))),
+        "hypot" => Some((FunctionOp::Hypot, 2, None)),
+        "min" => Some((FunctionOp::Min, 2, None)),
+        "max" => Some((FunctionOp::Max, 2, None)),
+        "log" => Some((FunctionOp::LogBase, 2, Some(2))),
+        "clamp" => Some((FunctionOp::Clamp, 3, Some(3))),
+        _ => None,
+    }
+}
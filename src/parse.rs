@@ -1,21 +1,52 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::{
-    ast::Expr,
+    ast::{Expr, Function, Parameter},
     autonum::AutoNum,
     consts::Definitions,
+    env::Environment,
     error::{get_token_str, ParseError},
     lex::{Lexer, Token, TokenData},
     operator::{
-        try_get_binary_operator, try_get_function, try_get_postfix_operator,
-        try_get_prefix_operator, BinaryOp, UnaryOp,
+        is_operator_word, try_get_binary_operator, try_get_function, try_get_multi_arg_function,
+        try_get_postfix_operator, try_get_prefix_operator, BinaryOp, UnaryOp,
     },
     query::is_query_keyword,
-    units::Quantity,
+    units::{Dimension, Quantity},
 };
 
 pub type ParseResult<'a> = Result<Expr, ParseError<'a>>;
 
-fn bracketed<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a> {
-    let expr = parse_expr(lexer, defs)?;
+#[derive(Clone)]
+pub struct Context<'a, 'b> {
+    pub defs: &'b Definitions<'a>,
+    variables: HashSet<String>,
+    functions: HashMap<String, usize>,
+}
+
+impl<'a, 'b> Context<'a, 'b> {
+    pub fn new(defs: &'b Definitions<'a>, env: &Environment) -> Context<'a, 'b> {
+        Context {
+            defs,
+            variables: env.variable_names().cloned().collect(),
+            functions: env
+                .function_arities()
+                .map(|(name, arity)| (name.clone(), arity))
+                .collect(),
+        }
+    }
+
+    pub fn declare_variable(&mut self, name: &str) {
+        self.variables.insert(String::from(name));
+    }
+
+    pub fn declare_function(&mut self, name: &str, arity: usize) {
+        self.functions.insert(String::from(name), arity);
+    }
+}
+
+fn bracketed<'a>(lexer: &mut Lexer<'a>, ctx: &Context<'a, '_>) -> ParseResult<'a> {
+    let expr = parse_expr(lexer, ctx)?;
     let rbrack = lexer.next_token()?;
     match rbrack.data {
         TokenData::RBracket => Ok(expr),
@@ -27,9 +58,148 @@ fn bracketed<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'
     }
 }
 
-fn root_n<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a> {
-    let n_token = lexer.next_token()?;
-    if let TokenData::Int(n) = n_token.data {
+fn interval<'a>(lexer: &mut Lexer<'a>, ctx: &Context<'a, '_>) -> ParseResult<'a> {
+    let lo = parse_expr(lexer, ctx)?;
+    let comma_token = lexer.next_token()?;
+    if !matches!(comma_token.data, TokenData::Sym(',')) {
+        return Err(ParseError::from_token(
+            String::from("expected ',' between the bounds of an interval"),
+            &comma_token,
+            lexer.original,
+        ));
+    }
+
+    let hi = parse_expr(lexer, ctx)?;
+    let rbrack_token = lexer.next_token()?;
+    match rbrack_token.data {
+        TokenData::Sym(']') => Ok(Expr::interval(lo, hi)),
+        _ => Err(ParseError::from_token(
+            format!("unexpected {}", get_token_str(&rbrack_token)),
+            &rbrack_token,
+            lexer.original,
+        )),
+    }
+}
+
+fn parse_arguments<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+) -> Result<Vec<Expr>, ParseError<'a>> {
+    let mut args = vec![parse_expr(lexer, ctx)?];
+    loop {
+        let token = lexer.next_token()?;
+        match token.data {
+            TokenData::Sym(',') => args.push(parse_expr(lexer, ctx)?),
+            TokenData::RBracket => return Ok(args),
+            _ => {
+                return Err(ParseError::from_token(
+                    format!("unexpected {}", get_token_str(&token)),
+                    &token,
+                    lexer.original,
+                ))
+            }
+        }
+    }
+}
+
+fn user_function_call<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    name: &'a str,
+    name_token: &Token<'a>,
+    arity: usize,
+) -> ParseResult<'a> {
+    let lbrack_token = lexer.next_token()?;
+    if !matches!(lbrack_token.data, TokenData::LBracket) {
+        return Err(ParseError::from_token(
+            String::from("expected left bracket '(' after function"),
+            &lbrack_token,
+            lexer.original,
+        ));
+    }
+
+    let args = parse_arguments(lexer, ctx)?;
+    if args.len() != arity {
+        return Err(ParseError::from_token(
+            format!(
+                "'{name}' takes {arity} argument(s) but {} were given",
+                args.len()
+            ),
+            name_token,
+            lexer.original,
+        ));
+    }
+    Ok(Expr::call(name, args))
+}
+
+fn builtin_function_call<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    name: &'a str,
+    name_token: &Token<'a>,
+) -> ParseResult<'a> {
+    let lbrack_token = lexer.next_token()?;
+    if !matches!(lbrack_token.data, TokenData::LBracket) {
+        return Err(ParseError::from_token(
+            String::from("expected left bracket '(' after function"),
+            &lbrack_token,
+            lexer.original,
+        ));
+    }
+
+    let mut args = parse_arguments(lexer, ctx)?;
+    let unary_op = try_get_function(name);
+    let multi_arg_op = try_get_multi_arg_function(name);
+    if args.len() == 1 {
+        if let Some(op) = unary_op {
+            return Ok(Expr::unary(op, args.remove(0)));
+        }
+    }
+
+    let expected = match multi_arg_op {
+        Some((op, min_args, max_args))
+            if args.len() >= min_args && max_args.is_none_or(|max| args.len() <= max) =>
+        {
+            return Ok(Expr::builtin(op, args));
+        }
+        None => String::from("1"),
+        Some((_, 2, Some(2))) if unary_op.is_some() => String::from("1 or 2"),
+        Some((_, min_args, Some(max_args))) if min_args == max_args => min_args.to_string(),
+        Some((_, min_args, _)) => format!("at least {min_args}"),
+    };
+    Err(ParseError::from_token(
+        format!(
+            "'{name}' takes {expected} argument(s) but {} were given",
+            args.len()
+        ),
+        name_token,
+        lexer.original,
+    ))
+}
+
+// Words are lexed together with any digits after them, so root3 is a single word
+fn is_root_word(word: &str) -> bool {
+    word.strip_prefix("root")
+        .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn root_n<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    word: &'a str,
+    word_token: &Token<'a>,
+) -> ParseResult<'a> {
+    let (n, n_token) = match &word["root".len()..] {
+        "" => {
+            let n_token = lexer.next_token()?;
+            match n_token.data {
+                TokenData::Int(n) => (Some(n), n_token),
+                _ => (None, n_token),
+            }
+        }
+        digits => (digits.parse::<i64>().ok(), word_token.clone()),
+    };
+    if let Some(n) = n {
         if n <= 0 {
             return Err(ParseError::from_token(
                 format!("cannot take {n}-root"),
@@ -53,7 +223,7 @@ fn root_n<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a>
             ));
         }
 
-        let inner = bracketed(lexer, defs)?;
+        let inner = bracketed(lexer, ctx)?;
         Ok(Expr::unary(UnaryOp::RootN(n as i8), inner))
     } else {
         Err(ParseError::from_pos(
@@ -66,43 +236,42 @@ fn root_n<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a>
 
 fn parse_word_at_start_of_atom<'a>(
     lexer: &mut Lexer<'a>,
-    defs: &Definitions<'a>,
+    ctx: &Context<'a, '_>,
     word: &'a str,
     word_token: &Token<'a>,
 ) -> ParseResult<'a> {
-    if word == "root" {
-        root_n(lexer, defs)
-    } else if let Some(op) = try_get_function(word) {
-        let token = lexer.next_token()?;
-        match token.data {
-            TokenData::LBracket => {
-                let inner = bracketed(lexer, defs)?;
-                Ok(Expr::unary(op, inner))
-            }
-            _ => Err(ParseError::from_token(
-                String::from("expected left bracket '(' after function"),
-                &token,
-                lexer.original,
-            )),
-        }
-    } else if let Some(c) = defs.constants.get(word) {
+    if is_root_word(word) {
+        root_n(lexer, ctx, word, word_token)
+    } else if try_get_function(word).is_some() || try_get_multi_arg_function(word).is_some() {
+        builtin_function_call(lexer, ctx, word, word_token)
+    } else if let Some(&arity) = ctx.functions.get(word) {
+        user_function_call(lexer, ctx, word, word_token, arity)
+    } else if ctx.variables.contains(word) {
+        Ok(Expr::Variable(String::from(word)))
+    } else if let Some(c) = ctx.defs.constants.get(word) {
         Ok(Expr::Quantity(c.clone()))
     } else {
         Err(ParseError::from_token(
-            format!("'{word}' is not a valid function or constant"),
+            format!("'{word}' is not a valid function, variable or constant"),
             word_token,
             lexer.original,
         ))
     }
 }
 
-fn atom<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a> {
+fn atom<'a>(lexer: &mut Lexer<'a>, ctx: &Context<'a, '_>) -> ParseResult<'a> {
     let token = lexer.next_token()?;
     match token.data {
         TokenData::Num(x) => Ok(Expr::Quantity(Quantity::dimensionless(AutoNum::Float(x)))),
         TokenData::Int(n) => Ok(Expr::Quantity(Quantity::dimensionless(AutoNum::Int(n)))),
-        TokenData::LBracket => bracketed(lexer, defs),
-        TokenData::Word(w) => parse_word_at_start_of_atom(lexer, defs, w, &token),
+        TokenData::Big(n) => Ok(Expr::Quantity(Quantity::dimensionless(AutoNum::Big(n)))),
+        TokenData::Duration(x) => Ok(Expr::Quantity(Quantity::new(
+            AutoNum::Float(x),
+            Dimension::new(0, 0, 1, 0, 0, 0, 0, 1),
+        ))),
+        TokenData::LBracket => bracketed(lexer, ctx),
+        TokenData::Sym('[') => interval(lexer, ctx),
+        TokenData::Word(w) => parse_word_at_start_of_atom(lexer, ctx, w, &token),
         _ => Err(ParseError::from_token(
             format!("unexpected {}", get_token_str(&token)),
             &token,
@@ -111,40 +280,96 @@ fn atom<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a> {
     }
 }
 
+// Decimal exponents must be exact fractions with a small denominator, such as 0.5 or 0.25.
+// Works on the literal's digits, since most such fractions have no exact f64
+fn decimal_to_fraction(literal: &str) -> Option<(i64, i64)> {
+    let literal = literal.replace(['_', '\''], "");
+    let (mantissa, exponent) = match literal.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (literal.as_str(), 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let mut numerator = format!("{whole}{fraction}").parse::<i128>().ok()?;
+    let scale = exponent.checked_sub(i32::try_from(fraction.len()).ok()?)?;
+    let power = 10i128.checked_pow(scale.unsigned_abs())?;
+    let mut denominator = 1;
+    if scale >= 0 {
+        numerator = numerator.checked_mul(power)?;
+    } else {
+        denominator = power;
+    }
+    (1..=127).find_map(|d| {
+        let n = numerator.checked_mul(d)?;
+        if n % denominator != 0 {
+            return None;
+        }
+        Some((i64::try_from(n / denominator).ok()?, d as i64))
+    })
+}
+
+// Unit exponents are integers or fractions, written as (n/d), as -n/d without spaces or as a
+// decimal. Gives the numerator and denominator
 fn parse_unit_exponent<'a>(
     lexer: &mut Lexer<'a>,
     units_str: &mut String,
-) -> Result<i8, ParseError<'a>> {
+) -> Result<(i64, i64), ParseError<'a>> {
+    let bracketed = matches!(lexer.peek_token()?.data, TokenData::LBracket);
+    if bracketed {
+        let _ = lexer.next_token();
+        units_str.push('(');
+    }
+    let negative = matches!(lexer.peek_token()?.data, TokenData::Sym('-'));
+    if negative {
+        let _ = lexer.next_token();
+        units_str.push('-');
+    }
+
     let token = lexer.next_token()?;
-    let exponent = match token.data {
+    let (numerator, denominator) = match token.data {
         TokenData::Int(n) => {
             units_str.push_str(token.substr);
-            n
-        }
-        TokenData::Sym('-') => {
-            units_str.push('-');
-            let num_token = lexer.next_token()?;
-            match num_token.data {
-                TokenData::Int(n) => {
-                    units_str.push_str(num_token.substr);
-                    -n
-                }
-                _ => {
-                    return Err(ParseError::from_token(
-                        format!(
-                            "unexpected {}, expected integer in exponent",
-                            get_token_str(&num_token)
-                        ),
-                        &num_token,
-                        lexer.original,
-                    ));
+            let slash = lexer.peek_token()?;
+            let denominator_token = lexer.peek_second_token()?;
+            match (&slash.data, &denominator_token.data) {
+                (TokenData::Sym('/'), &TokenData::Int(d))
+                    if bracketed
+                        || negative
+                            && slash.start_pos == token.end_pos
+                            && denominator_token.start_pos == slash.end_pos =>
+                {
+                    let _ = lexer.next_token();
+                    let _ = lexer.next_token();
+                    units_str.push('/');
+                    units_str.push_str(denominator_token.substr);
+                    if d == 0 {
+                        return Err(ParseError::from_token(
+                            String::from("exponent has a denominator of 0"),
+                            &denominator_token,
+                            lexer.original,
+                        ));
+                    }
+                    (n, d)
                 }
+                _ => (n, 1),
             }
         }
+        TokenData::Num(_) => {
+            units_str.push_str(token.substr);
+            decimal_to_fraction(token.substr).ok_or_else(|| {
+                ParseError::from_token(
+                    format!(
+                        "exponent '{}' is not an exact fraction with a denominator of at most 127",
+                        token.substr
+                    ),
+                    &token,
+                    lexer.original,
+                )
+            })?
+        }
         _ => {
             return Err(ParseError::from_token(
                 format!(
-                    "unexpected {}, expected integer in exponent",
+                    "unexpected {}, expected number in exponent",
                     get_token_str(&token)
                 ),
                 &token,
@@ -153,46 +378,139 @@ fn parse_unit_exponent<'a>(
         }
     };
 
-    if exponent > 127 || exponent < -128 {
+    if bracketed {
+        let rbrack = lexer.next_token()?;
+        if !matches!(rbrack.data, TokenData::RBracket) {
+            return Err(ParseError::from_token(
+                format!("unexpected {}, expected ')'", get_token_str(&rbrack)),
+                &rbrack,
+                lexer.original,
+            ));
+        }
+        units_str.push(')');
+    }
+
+    if denominator > 127 {
+        return Err(ParseError::from_token(
+            format!("denominator of '{denominator}' exceeds maximum (max: 127)"),
+            &token,
+            lexer.original,
+        ));
+    }
+    if !(-128..=127).contains(&numerator) {
         return Err(ParseError::from_token(
             format!(
                 "magnitude of '{}' exceeds maximum (max: -127 to 128)",
                 token.substr
             ),
             &token,
-            &lexer.original,
+            lexer.original,
         ));
     }
+    Ok(if negative {
+        (-numerator, denominator)
+    } else {
+        (numerator, denominator)
+    })
+}
 
-    Ok(exponent as i8)
+// Words that can follow units without being part of them
+fn ends_units(w: &str) -> bool {
+    is_query_keyword(w) || is_operator_word(w) || w == "per"
 }
 
-pub fn parse_units<'a>(
+// A '%' followed by an integer or a bracket is a modulo rather than a percentage, since a
+// percentage can be followed by other operands, as in 50% pi
+fn starts_modulo_operand(data: &TokenData) -> bool {
+    matches!(
+        data,
+        TokenData::Int(_) | TokenData::Big(_) | TokenData::LBracket
+    )
+}
+
+pub fn parse_affine_unit<'a>(
     lexer: &mut Lexer<'a>,
     defs: &Definitions<'a>,
-) -> Result<(String, Quantity), ParseError<'a>> {
-    let mut units_quantity = Quantity::dimensionless(AutoNum::Float(1.0));
-    let mut units_str = String::new();
-    let mut first = true;
+) -> Result<Option<(&'a str, Quantity, AutoNum)>, ParseError<'a>> {
+    let TokenData::Word(w) = lexer.peek_token()?.data else {
+        return Ok(None);
+    };
+    let Some((scale, offset)) = defs.get_affine_unit(w) else {
+        return Ok(None);
+    };
 
-    while let (TokenData::Word(w), token) = {
-        let token = lexer.peek_token()?;
-        (token.data.clone(), token)
-    } {
-        if is_query_keyword(w) || w == "per" {
-            return Ok((units_str, units_quantity));
-        }
+    let _ = lexer.next_token();
+    let token = lexer.peek_token()?;
+    match token.data {
+        TokenData::Word(next) if !ends_units(next) => Err(ParseError::from_token(
+            format!("'{w}' cannot be combined with other units, use K instead"),
+            &token,
+            lexer.original,
+        )),
+        TokenData::Sym('^') => Err(ParseError::from_token(
+            format!("'{w}' cannot be raised to a power, use K instead"),
+            &token,
+            lexer.original,
+        )),
+        _ => Ok(Some((w, scale, offset))),
+    }
+}
 
-        if !first {
-            units_str.push(' ');
+// Whether a unit, possibly inside brackets, starts after skipping some tokens. Units that
+// share a name with a constant are only counted when allowed, and local names shadow units
+fn unit_follows(
+    lexer: &Lexer,
+    defs: &Definitions,
+    locals: Option<&HashSet<String>>,
+    skip: usize,
+    allow_constants: bool,
+) -> bool {
+    let mut lookahead = lexer.clone();
+    for _ in 0..skip {
+        let _ = lookahead.next_token();
+    }
+    loop {
+        match lookahead.next_token().map(|t| t.data) {
+            Ok(TokenData::LBracket | TokenData::Word("sqrt" | "cbrt")) => continue,
+            Ok(TokenData::Word(w)) => {
+                return defs.get_unit(w).is_some()
+                    && !locals.is_some_and(|locals| locals.contains(w))
+                    && (allow_constants || !defs.constants.contains_key(w))
+            }
+            _ => return false,
         }
+    }
+}
 
-        let _ = lexer.next_token();
-        let base_unit = match defs.get_unit(w) {
+fn parse_unit_factor<'a>(
+    lexer: &mut Lexer<'a>,
+    defs: &Definitions<'a>,
+    locals: Option<&HashSet<String>>,
+    units_str: &mut String,
+) -> Result<Quantity, ParseError<'a>> {
+    let token = lexer.next_token()?;
+    let base_unit = match token.data {
+        TokenData::Word(w @ ("sqrt" | "cbrt"))
+            if matches!(lexer.peek_token()?.data, TokenData::LBracket) =>
+        {
+            units_str.push_str(w);
+            let root = if w == "sqrt" { 2 } else { 3 };
+            return parse_unit_factor(lexer, defs, locals, units_str)?
+                .pow_quantity(&Quantity::dimensionless(AutoNum::Rational(1, root)))
+                .map_err(|e| ParseError::from_token(e.error, &token, lexer.original));
+        }
+        TokenData::Word(w) => match defs.get_unit(w) {
             Some(u) => {
                 units_str.push_str(w);
                 u
             }
+            None if defs.get_affine_unit(w).is_some() => {
+                return Err(ParseError::from_token(
+                    format!("'{w}' cannot be combined with other units, use K instead"),
+                    &token,
+                    lexer.original,
+                ));
+            }
             None => {
                 return Err(ParseError::from_token(
                     format!("'{w}' is not a valid unit"),
@@ -200,36 +518,165 @@ pub fn parse_units<'a>(
                     lexer.original,
                 ));
             }
+        },
+        TokenData::LBracket => {
+            units_str.push('(');
+            let group = parse_unit_quotient(lexer, defs, locals, units_str)?;
+            let rbrack = lexer.next_token()?;
+            if !matches!(rbrack.data, TokenData::RBracket) {
+                return Err(ParseError::from_token(
+                    format!("unexpected {}, expected ')'", get_token_str(&rbrack)),
+                    &rbrack,
+                    lexer.original,
+                ));
+            }
+            units_str.push(')');
+            group
+        }
+        _ => {
+            return Err(ParseError::from_token(
+                format!("unexpected {}, expected units", get_token_str(&token)),
+                &token,
+                lexer.original,
+            ));
+        }
+    };
+    if !matches!(lexer.peek_token()?.data, TokenData::Sym('^')) {
+        return Ok(base_unit);
+    }
+
+    units_str.push('^');
+    let _ = lexer.next_token();
+    let (numerator, denominator) = parse_unit_exponent(lexer, units_str)?;
+    let exponent = AutoNum::rational(numerator as i128, denominator as i128)
+        .unwrap_or(AutoNum::Int(numerator));
+    base_unit
+        .pow_quantity(&Quantity::dimensionless(exponent))
+        .map_err(|e| ParseError::from_token(e.error, &token, lexer.original))
+}
+
+// Juxtaposed units are multiplied, binding tighter than '/' and '*' so that J/kg K is J/(kg K).
+// The product stops at a local name, which is then a factor rather than a unit
+fn parse_unit_product<'a>(
+    lexer: &mut Lexer<'a>,
+    defs: &Definitions<'a>,
+    locals: Option<&HashSet<String>>,
+    units_str: &mut String,
+) -> Result<Quantity, ParseError<'a>> {
+    let mut product = parse_unit_factor(lexer, defs, locals, units_str)?;
+    loop {
+        let token = lexer.peek_token()?;
+        match token.data {
+            TokenData::Word(w)
+                if !ends_units(w) && !locals.is_some_and(|locals| locals.contains(w)) => {}
+            _ => return Ok(product),
+        }
+        units_str.push(' ');
+        let factor = parse_unit_factor(lexer, defs, locals, units_str)?;
+        product = product
+            .mul_quantity(&factor)
+            .map_err(|e| ParseError::from_token(e.error, &token, lexer.original))?;
+    }
+}
+
+// A '/' or '*' only continues the units when a unit follows it, so 5 m / 2 is still a division.
+// Names such as g are read as units after either operator, so 2 m/g and 2 m*g both use grams
+fn parse_unit_quotient<'a>(
+    lexer: &mut Lexer<'a>,
+    defs: &Definitions<'a>,
+    locals: Option<&HashSet<String>>,
+    units_str: &mut String,
+) -> Result<Quantity, ParseError<'a>> {
+    let mut units = parse_unit_product(lexer, defs, locals, units_str)?;
+    loop {
+        let token = lexer.peek_token()?;
+        let divide = match token.data {
+            TokenData::Sym('/') => true,
+            TokenData::Sym('*') => false,
+            _ => return Ok(units),
         };
-        if !matches!(lexer.peek_token()?.data, TokenData::Sym('^')) {
-            units_quantity = units_quantity
-                .mul_quantity(&base_unit)
-                .map_err(|e| ParseError::from_token(e.error, &token, lexer.original))?;
-            continue;
+        if !unit_follows(lexer, defs, locals, 1, true) {
+            return Ok(units);
         }
 
-        units_str.push('^');
         let _ = lexer.next_token();
-        let exponent = parse_unit_exponent(lexer, &mut units_str)?;
-        let exponentiated_unit = base_unit.units.pow(exponent);
-        let exponentiated_value = base_unit.value.auto_pow(&AutoNum::Int(exponent as i64));
-        units_quantity = exponentiated_unit
-            .and_then(|d| units_quantity.mul_quantity(&Quantity::new(exponentiated_value, d)))
-            .map_err(|e| ParseError::from_token(e.error, &token, lexer.original))?;
-        first = false;
+        units_str.push(if divide { '/' } else { '*' });
+        let rhs = parse_unit_product(lexer, defs, locals, units_str)?;
+        let combined = if divide {
+            units
+                .value
+                .auto_div(&rhs.value)
+                .and_then(|value| Ok(Quantity::new(value, units.units.combine(&rhs.units, true)?)))
+        } else {
+            units.mul_quantity(&rhs)
+        };
+        units = combined.map_err(|e| ParseError::from_token(e.error, &token, lexer.original))?;
     }
-    Ok((units_str, units_quantity))
 }
 
-fn postfixed<'a>(
+pub fn parse_units<'a>(
     lexer: &mut Lexer<'a>,
     defs: &Definitions<'a>,
+) -> Result<(String, Quantity), ParseError<'a>> {
+    parse_units_with(lexer, defs, None)
+}
+
+// Units after an operand, where variables and parameters are not read as units
+fn parse_units_with<'a>(
+    lexer: &mut Lexer<'a>,
+    defs: &Definitions<'a>,
+    locals: Option<&HashSet<String>>,
+) -> Result<(String, Quantity), ParseError<'a>> {
+    let mut units_str = String::new();
+    match lexer.peek_token()?.data {
+        TokenData::Word(w) if !ends_units(w) => {}
+        TokenData::LBracket => {}
+        _ => return Ok((units_str, Quantity::dimensionless(AutoNum::Float(1.0)))),
+    }
+    let units = parse_unit_quotient(lexer, defs, locals, &mut units_str)?;
+    Ok((units_str, units))
+}
+
+// Words after an operand are units, unless they name a variable or parameter, or only name
+// something that can start an atom
+fn is_implicit_factor_word(ctx: &Context, w: &str) -> bool {
+    if ctx.variables.contains(w) {
+        return true;
+    }
+    if ctx.defs.get_unit(w).is_some() || ctx.defs.get_affine_unit(w).is_some() {
+        return false;
+    }
+    is_root_word(w)
+        || try_get_function(w).is_some()
+        || try_get_multi_arg_function(w).is_some()
+        || ctx.functions.contains_key(w)
+        || ctx.defs.constants.contains_key(w)
+}
+
+// Juxtaposed operands are multiplied, binding tighter than '*' and '/' so that 1/2pi is 1/(2 pi)
+fn implicit_product<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    operand: Expr,
+) -> ParseResult<'a> {
+    let factor = postfixed(lexer, ctx, true)?;
+    Ok(Expr::binary(BinaryOp::Mul, operand, factor))
+}
+
+fn postfixed<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
     consume_postfix_words: bool,
 ) -> ParseResult<'a> {
-    let mut operand = atom(lexer, defs)?;
+    // durations such as 1:30:00 are already in seconds
+    let is_duration = matches!(lexer.peek_token()?.data, TokenData::Duration(_));
+    let mut operand = atom(lexer, ctx)?;
     loop {
         let token = lexer.peek_token()?;
         match token.data {
+            TokenData::Sym('%') if starts_modulo_operand(&lexer.peek_second_token()?.data) => {
+                return Ok(operand);
+            }
             TokenData::Sym(c) => {
                 if let Some(op) = try_get_postfix_operator(c) {
                     let _ = lexer.next_token();
@@ -237,15 +684,35 @@ fn postfixed<'a>(
                     continue;
                 } else if c == '^' {
                     let _ = lexer.next_token();
-                    let power = prefixed(lexer, defs, false)?;
+                    let power = prefixed(lexer, ctx, false)?;
                     operand = Expr::binary(BinaryOp::Pow, operand, power);
                 } else {
                     return Ok(operand);
                 }
             }
-            TokenData::Word(w) if consume_postfix_words && !is_query_keyword(w) => {
-                let (_, units) = parse_units(lexer, defs)?;
-                return Ok(Expr::with_units(operand, units));
+            TokenData::Word(w) if consume_postfix_words && !ends_units(w) => {
+                if is_implicit_factor_word(ctx, w)
+                    && !unit_follows(lexer, ctx.defs, Some(&ctx.variables), 0, false)
+                {
+                    return implicit_product(lexer, ctx, operand);
+                }
+                if is_duration {
+                    return Err(duration_units_error(&token, lexer));
+                }
+                if let Some((_, scale, offset)) = parse_affine_unit(lexer, ctx.defs)? {
+                    return Ok(Expr::with_affine_units(operand, scale, offset));
+                }
+                return postfix_units(lexer, ctx, operand);
+            }
+            TokenData::LBracket if consume_postfix_words => {
+                // brackets starting with a unit hold units rather than a factor
+                if !unit_follows(lexer, ctx.defs, Some(&ctx.variables), 0, false) {
+                    return implicit_product(lexer, ctx, operand);
+                }
+                if is_duration {
+                    return Err(duration_units_error(&token, lexer));
+                }
+                return postfix_units(lexer, ctx, operand);
             }
             _ => {
                 return Ok(operand);
@@ -254,9 +721,31 @@ fn postfixed<'a>(
     }
 }
 
+fn duration_units_error<'a>(token: &Token<'a>, lexer: &Lexer<'a>) -> ParseError<'a> {
+    ParseError::from_token(
+        String::from("a duration cannot be followed by units"),
+        token,
+        lexer.original,
+    )
+}
+
+// Units after an operand, which may be followed by a variable or parameter as in 2 kg m
+fn postfix_units<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    operand: Expr,
+) -> ParseResult<'a> {
+    let (_, units) = parse_units_with(lexer, ctx.defs, Some(&ctx.variables))?;
+    let operand = Expr::with_units(operand, units);
+    match lexer.peek_token()?.data {
+        TokenData::Word(w) if ctx.variables.contains(w) => implicit_product(lexer, ctx, operand),
+        _ => Ok(operand),
+    }
+}
+
 fn prefixed<'a>(
     lexer: &mut Lexer<'a>,
-    defs: &Definitions<'a>,
+    ctx: &Context<'a, '_>,
     consume_postfix_words: bool,
 ) -> ParseResult<'a> {
     let token = lexer.peek_token()?;
@@ -264,37 +753,34 @@ fn prefixed<'a>(
         TokenData::Sym(c) => match try_get_prefix_operator(c) {
             Some(op) => {
                 let _ = lexer.next_token();
-                let operand = prefixed(lexer, defs, consume_postfix_words)?;
-                Ok(Expr::unary(op, operand))
+                let operand = prefixed(lexer, ctx, consume_postfix_words)?;
+                Ok(Expr::prefixed(op, operand))
             }
-            None => postfixed(lexer, defs, consume_postfix_words),
+            None => postfixed(lexer, ctx, consume_postfix_words),
         },
-        _ => postfixed(lexer, defs, consume_postfix_words),
+        _ => postfixed(lexer, ctx, consume_postfix_words),
     }
 }
 
-fn pratt<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>, prev_prec: u8) -> ParseResult<'a> {
-    let mut lhs = prefixed(lexer, defs, true)?;
+fn pratt<'a>(lexer: &mut Lexer<'a>, ctx: &Context<'a, '_>, prev_prec: u8) -> ParseResult<'a> {
+    let mut lhs = prefixed(lexer, ctx, true)?;
 
     while let Some((op, prec, r_assoc)) = {
         let token = lexer.peek_token()?;
-        match token.data {
-            TokenData::Sym(c) => try_get_binary_operator(c),
-            _ => None,
-        }
+        try_get_binary_operator(&token.data)
     } {
         if prec < prev_prec || (prec == prev_prec && !r_assoc) {
             break;
         }
         let _ = lexer.next_token();
-        let rhs = pratt(lexer, defs, prec)?;
+        let rhs = pratt(lexer, ctx, prec)?;
         lhs = Expr::binary(op, lhs, rhs);
     }
 
     Ok(lhs)
 }
 
-pub fn parse_expr<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseResult<'a> {
+pub fn parse_expr<'a>(lexer: &mut Lexer<'a>, ctx: &Context<'a, '_>) -> ParseResult<'a> {
     let has_undim_prefix = match lexer.peek_token()?.data {
         TokenData::Sym(':') => {
             let _ = lexer.next_token();
@@ -302,11 +788,11 @@ pub fn parse_expr<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseRes
         }
         _ => false,
     };
-    let inner_expr = pratt(lexer, defs, 0)?;
+    let inner_expr = pratt(lexer, ctx, 0)?;
     let postfixed_expr = match lexer.peek_token()?.data {
         TokenData::Word("per") => {
             let _ = lexer.next_token();
-            let (_, unit) = parse_units(lexer, defs)?;
+            let (_, unit) = parse_units(lexer, ctx.defs)?;
             Expr::binary(BinaryOp::Div, inner_expr, Expr::Quantity(unit))
         }
         _ => inner_expr,
@@ -318,3 +804,176 @@ pub fn parse_expr<'a>(lexer: &mut Lexer<'a>, defs: &Definitions<'a>) -> ParseRes
         Ok(postfixed_expr)
     }
 }
+
+fn is_reserved_word(word: &str) -> bool {
+    is_root_word(word)
+        || word == "per"
+        || is_query_keyword(word)
+        || is_operator_word(word)
+        || try_get_function(word).is_some()
+        || try_get_multi_arg_function(word).is_some()
+}
+
+fn check_definable_name<'a>(
+    lexer: &Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    name: &'a str,
+    token: &Token<'a>,
+) -> Result<(), ParseError<'a>> {
+    let error = if is_reserved_word(name) {
+        format!("cannot define '{name}', it is a reserved function or keyword")
+    } else if ctx.defs.get_unit(name).is_some() {
+        format!("cannot define '{name}', it is already the name of a unit")
+    } else {
+        return Ok(());
+    };
+    Err(ParseError::from_token(error, token, lexer.original))
+}
+
+pub fn parse_assignment_target<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+) -> Result<Option<&'a str>, ParseError<'a>> {
+    let token = lexer.peek_token()?;
+    let TokenData::Word(name) = token.data else {
+        return Ok(None);
+    };
+    if !matches!(lexer.peek_second_token()?.data, TokenData::Sym('=')) {
+        return Ok(None);
+    }
+
+    check_definable_name(lexer, ctx, name, &token)?;
+    if ctx.functions.contains_key(name) {
+        return Err(ParseError::from_token(
+            format!("cannot assign to '{name}', it is already a function"),
+            &token,
+            lexer.original,
+        ));
+    }
+
+    let _ = lexer.next_token();
+    let _ = lexer.next_token();
+    Ok(Some(name))
+}
+
+fn is_function_definition(lexer: &Lexer) -> bool {
+    let mut lookahead = lexer.clone();
+    let _ = lookahead.next_token();
+    let _ = lookahead.next_token();
+    let mut depth = 1;
+    while depth > 0 {
+        match lookahead.next_token().map(|t| t.data) {
+            Ok(TokenData::LBracket) => depth += 1,
+            Ok(TokenData::RBracket) => depth -= 1,
+            Ok(TokenData::EndOfInput) | Err(_) => return false,
+            _ => {}
+        }
+    }
+    matches!(
+        lookahead.next_token().map(|t| t.data),
+        Ok(TokenData::Sym('='))
+    )
+}
+
+fn parse_parameter<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+    params: &[Parameter],
+) -> Result<Parameter, ParseError<'a>> {
+    let token = lexer.next_token()?;
+    let TokenData::Word(name) = token.data else {
+        return Err(ParseError::from_token(
+            format!(
+                "unexpected {}, expected parameter name",
+                get_token_str(&token)
+            ),
+            &token,
+            lexer.original,
+        ));
+    };
+    if is_reserved_word(name) {
+        return Err(ParseError::from_token(
+            format!("cannot use '{name}' as a parameter, it is a reserved function or keyword"),
+            &token,
+            lexer.original,
+        ));
+    } else if params.iter().any(|p| p.name == name) {
+        return Err(ParseError::from_token(
+            format!("parameter '{name}' specified more than once"),
+            &token,
+            lexer.original,
+        ));
+    }
+
+    if !matches!(lexer.peek_token()?.data, TokenData::Sym(':')) {
+        return Ok(Parameter {
+            name: String::from(name),
+            units: None,
+        });
+    }
+    let _ = lexer.next_token();
+    let units_token = lexer.peek_token()?;
+    let units = parse_units(lexer, ctx.defs)?;
+    if units.0.is_empty() {
+        return Err(ParseError::from_token(
+            format!("unexpected {}, expected units", get_token_str(&units_token)),
+            &units_token,
+            lexer.original,
+        ));
+    }
+    Ok(Parameter {
+        name: String::from(name),
+        units: Some(units),
+    })
+}
+
+pub fn parse_function_definition<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &Context<'a, '_>,
+) -> Result<Option<(&'a str, Function)>, ParseError<'a>> {
+    let token = lexer.peek_token()?;
+    let TokenData::Word(name) = token.data else {
+        return Ok(None);
+    };
+    if !matches!(lexer.peek_second_token()?.data, TokenData::LBracket)
+        || !is_function_definition(lexer)
+    {
+        return Ok(None);
+    }
+
+    check_definable_name(lexer, ctx, name, &token)?;
+    if ctx.variables.contains(name) {
+        return Err(ParseError::from_token(
+            format!("cannot define '{name}', it is already a variable"),
+            &token,
+            lexer.original,
+        ));
+    }
+
+    let _ = lexer.next_token();
+    let _ = lexer.next_token();
+    let mut params = Vec::new();
+    loop {
+        params.push(parse_parameter(lexer, ctx, &params)?);
+        let token = lexer.next_token()?;
+        match token.data {
+            TokenData::Sym(',') => continue,
+            TokenData::RBracket => break,
+            _ => {
+                return Err(ParseError::from_token(
+                    format!("unexpected {}", get_token_str(&token)),
+                    &token,
+                    lexer.original,
+                ))
+            }
+        }
+    }
+    let _ = lexer.next_token();
+
+    let mut body_ctx = ctx.clone();
+    for param in &params {
+        body_ctx.declare_variable(&param.name);
+    }
+    let body = parse_expr(lexer, &body_ctx)?;
+    Ok(Some((name, Function { params, body })))
+}
@@ -1,16 +1,41 @@
+use std::rc::Rc;
+
 use crate::{
-    ast::{EvalError, Expr},
+    ast::{EvalError, Expr, Statement},
     autonum::AutoNum,
-    consts::Definitions,
+    bigint::BigInt,
+    complex::Complex,
+    consts::{get_engineering_prefix, Definitions},
+    env::Environment,
     error::{get_token_str, ParseError},
     lex::{Lexer, Token, TokenData},
-    parse::{parse_expr, parse_units},
-    units::Quantity,
+    parse::{
+        parse_affine_unit, parse_assignment_target, parse_expr, parse_function_definition,
+        parse_units, Context,
+    },
+    units::{Dimension, Quantity, TemperatureKind},
 };
 
-const QUERY_KEYWORDS: [&'static str; 9] = [
+const QUERY_KEYWORDS: [&str; 26] = [
     "in",
+    "si",
+    "autoprefix",
+    "ap",
     "round",
+    "sigfig",
+    "sf",
+    "fraction",
+    "frac",
+    "mixed",
+    "digits",
+    "complex",
+    "polar",
+    "hex",
+    "bin",
+    "oct",
+    "base",
+    "group",
+    "time",
     "fixed",
     "scientific",
     "hideunits",
@@ -27,15 +52,77 @@ pub fn is_query_keyword(s: &str) -> bool {
 enum ScientificDisplay {
     Never,
     Always(bool),
+    Engineering(bool),
     Exceeds(f64, f64),
 }
 
+#[derive(Clone, Copy)]
+enum FractionDisplay {
+    Improper,
+    Mixed,
+}
+
+#[derive(Clone, Copy)]
+enum DigitsDisplay {
+    All,
+    Count,
+}
+
+// Separators between groups of thousands, and the decimal point to use with them
+#[derive(Clone, Copy, PartialEq)]
+pub enum GroupDisplay {
+    Comma,
+    Space,
+    European,
+    Off,
+}
+
+impl GroupDisplay {
+    pub fn from_name(name: &str) -> Option<GroupDisplay> {
+        match name {
+            "comma" => Some(GroupDisplay::Comma),
+            "space" => Some(GroupDisplay::Space),
+            "european" | "eu" => Some(GroupDisplay::European),
+            "none" => Some(GroupDisplay::Off),
+            _ => None,
+        }
+    }
+}
+
+// Integer output in another base, optionally as a two's complement number of the given width
+struct BaseDisplay {
+    keyword: &'static str,
+    radix: u32,
+    width: Option<u32>,
+}
+
+struct TargetUnits {
+    name: String,
+    value: Quantity,
+    offset: Option<AutoNum>,
+    prefixable: bool,
+}
+
 pub struct Query {
-    expr: Expr,
-    unit: Option<(String, Quantity)>,
+    statements: Vec<Statement>,
+    expr: Option<Expr>,
+    unit: Option<TargetUnits>,
+    mixed_units: Vec<TargetUnits>,
     round: Option<(usize, bool)>,
+    sigfig: Option<usize>,
+    fraction: Option<FractionDisplay>,
+    digits: Option<DigitsDisplay>,
+    complex: bool,
+    polar: Option<bool>,
+    base: Option<BaseDisplay>,
+    group: Option<GroupDisplay>,
+    time: bool,
+    default_group: Option<GroupDisplay>,
     scientific: Option<ScientificDisplay>,
     nounits: bool,
+    si_units: bool,
+    autoprefix: bool,
+    derived_units: Vec<(&'static str, Dimension)>,
 }
 
 fn get_scientific(x: f64, n: usize, fixed: bool) -> String {
@@ -78,7 +165,157 @@ fn get_scientific(x: f64, n: usize, fixed: bool) -> String {
     )
 }
 
+// Like scientific notation, but the exponent is always a multiple of 3 so that there
+// are 1 to 3 digits before the decimal point. The fixed layout is padded to 3 digits
+fn get_engineering(x: f64, n: usize, fixed: bool) -> String {
+    let fixed_len = match n {
+        0 => 9,
+        _ => n + 10,
+    };
+    if !x.is_finite() {
+        let special = if x.is_nan() {
+            "NaN"
+        } else if x > 0.0 {
+            "inf"
+        } else {
+            "-inf"
+        };
+        return if fixed {
+            format!("{special:>fixed_len$}")
+        } else {
+            String::from(special)
+        };
+    }
+
+    let mut exponent = match x {
+        0.0 => 0,
+        _ => (x.abs().log10().floor() as i32).div_euclid(3) * 3,
+    };
+    let mut mantissa = format!("{:.n$}", x / 10f64.powi(exponent));
+    if mantissa
+        .trim_start_matches('-')
+        .parse::<f64>()
+        .unwrap_or(0.0)
+        >= 1000.0
+    {
+        exponent += 3;
+        mantissa = format!("{:.n$}", x / 10f64.powi(exponent));
+    }
+
+    if !fixed {
+        return format!("{mantissa}e{exponent}");
+    }
+
+    let unsigned_mantissa = mantissa.trim_start_matches('-');
+    let integer_len = unsigned_mantissa
+        .find('.')
+        .unwrap_or(unsigned_mantissa.len());
+    format!(
+        "{}{}{unsigned_mantissa}e{}{:03}",
+        " ".repeat(3 - integer_len),
+        if x.is_sign_negative() { '-' } else { '+' },
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+// Adds to the exponent of a number already formatted in scientific notation
+fn shift_exponent(s: &str, shift: usize, fixed: bool) -> String {
+    let Some((mantissa, exponent)) = s.rsplit_once('e') else {
+        return String::from(s);
+    };
+    let exponent = exponent.parse::<i64>().unwrap_or(0) + shift as i64;
+    if fixed {
+        format!(
+            "{mantissa}e{}{:03}",
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else {
+        format!("{mantissa}e{exponent}")
+    }
+}
+
+// The digits of the absolute value of n, using uppercase letters past 9
+fn get_radix_digits(n: &BigInt, radix: u32) -> String {
+    let mut digits = Vec::new();
+    let mut n = n.clone();
+    loop {
+        let (quotient, digit) = n.div_rem_small(radix);
+        digits.push(
+            char::from_digit(digit, radix)
+                .unwrap_or('?')
+                .to_ascii_uppercase(),
+        );
+        if quotient.is_zero() {
+            break;
+        }
+        n = quotient;
+    }
+    digits.iter().rev().collect()
+}
+
+// Separates digits into groups of the given size, counting from the right
+fn group_digits(digits: &str, size: usize, separator: char) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(size) {
+            grouped.push(separator);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+// Groups the integer part of a number that is not in scientific notation into thousands
+fn group_thousands(s: &str, separator: char, decimal_point: char) -> String {
+    let (sign, unsigned) = match s.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", s),
+    };
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+    if !integer.bytes().all(|b| b.is_ascii_digit()) {
+        return String::from(s);
+    }
+
+    let mut grouped = format!("{sign}{}", group_digits(integer, 3, separator));
+    if let Some(fraction) = fraction {
+        grouped.push(decimal_point);
+        grouped.push_str(fraction);
+    }
+    grouped
+}
+
+// Rounds x to n significant figures, also returning how many decimal places are needed to show them
+fn round_significant(x: f64, n: usize) -> (f64, usize) {
+    if x == 0.0 || !x.is_finite() {
+        return (x, n - 1);
+    }
+    let rounded = format!("{x:.*e}", n - 1).parse::<f64>().unwrap_or(x);
+    let magnitude = rounded.abs().log10().floor() as i64;
+    (rounded, (n as i64 - 1 - magnitude).max(0) as usize)
+}
+
 impl Query {
+    fn get_group(&self) -> GroupDisplay {
+        self.group
+            .or(self.default_group)
+            .unwrap_or(GroupDisplay::Off)
+    }
+
+    // Never used on numbers in scientific notation, so mantissas are left alone
+    fn get_grouped_str(&self, s: String) -> String {
+        match self.get_group() {
+            GroupDisplay::Comma => group_thousands(&s, ',', '.'),
+            GroupDisplay::Space => group_thousands(&s, ' ', '.'),
+            GroupDisplay::European => group_thousands(&s, '.', ','),
+            GroupDisplay::Off => s,
+        }
+    }
+
     fn requires_scientific_notation(&self, x: f64) -> (bool, bool) {
         match self
             .scientific
@@ -86,7 +323,9 @@ impl Query {
             .unwrap_or(ScientificDisplay::Exceeds(1e-5, 1e10))
         {
             ScientificDisplay::Never => (false, false),
-            ScientificDisplay::Always(fixed) => (true, fixed),
+            ScientificDisplay::Always(fixed) | ScientificDisplay::Engineering(fixed) => {
+                (true, fixed)
+            }
             ScientificDisplay::Exceeds(lo, hi) => {
                 let y = x.abs();
                 (y >= hi || y <= lo && y != 0.0, false)
@@ -94,76 +333,649 @@ impl Query {
         }
     }
 
+    fn get_scientific_str(&self, x: f64, n: usize, fixed: bool) -> String {
+        match self.scientific {
+            Some(ScientificDisplay::Engineering(_)) => get_engineering(x, n, fixed),
+            _ => get_scientific(x, n, fixed),
+        }
+    }
+
+    // Trailing zeros are kept since they are significant
+    fn get_significant_str(&self, x: f64, n: usize, scientific: bool, fixed: bool) -> String {
+        if !scientific {
+            let (rounded, decimals) = round_significant(x, n);
+            return self.get_grouped_str(format!("{rounded:.decimals$}"));
+        }
+
+        let (rounded, _) = round_significant(x, n);
+        let decimals = match self.scientific {
+            Some(ScientificDisplay::Engineering(_)) if rounded != 0.0 => {
+                let magnitude = rounded.abs().log10().floor() as i64;
+                (n - 1).saturating_sub(magnitude.rem_euclid(3) as usize)
+            }
+            _ => n - 1,
+        };
+        self.get_scientific_str(rounded, decimals, fixed)
+    }
+
+    // The power of ten of the last digit that get_float_str shows for x
+    fn get_last_digit_exponent(&self, x: f64) -> Option<i32> {
+        if x == 0.0 || !x.is_finite() {
+            return None;
+        }
+        let magnitude = x.abs().log10().floor() as i32;
+        if let Some(n) = self.sigfig {
+            return Some(magnitude - (n as i32 - 1));
+        }
+        let (scientific, _) = self.requires_scientific_notation(x);
+        let rounding = self
+            .round
+            .map_or(if scientific { 4 } else { 8 }, |(n, _)| n) as i32;
+        match (scientific, &self.scientific) {
+            (false, _) => Some(-rounding),
+            (true, Some(ScientificDisplay::Engineering(_))) => {
+                Some(magnitude.div_euclid(3) * 3 - rounding)
+            }
+            (true, _) => Some(magnitude - rounding),
+        }
+    }
+
+    // Interval bounds are rounded outwards to the displayed precision, so that the displayed
+    // interval still contains the result. Rounding can carry into a new leading digit, which
+    // makes the last digit coarser, so the rounding is repeated until it settles
+    fn get_bound_str(&self, x: f64, down: bool) -> String {
+        // Dividing by an exact power of ten keeps decimals correctly rounded, and large powers
+        // are split so that they do not overflow
+        let scale = |k: f64, e: i32| {
+            let (e1, e2) = (e / 2, e - e / 2);
+            match e {
+                0..=22 => k * 10f64.powi(e),
+                -22..0 => k / 10f64.powi(-e),
+                _ => k * 10f64.powi(e1) * 10f64.powi(e2),
+            }
+        };
+        let mut bound = x;
+        let mut last_exponent = None;
+        while let Some(e) = self
+            .get_last_digit_exponent(bound)
+            .filter(|&e| last_exponent.is_none_or(|last| e > last))
+        {
+            last_exponent = Some(e);
+            let k = scale(x, -e).round();
+            bound = match scale(k, e) {
+                y if down && y > x => scale(k - 1.0, e),
+                y if !down && y < x => scale(k + 1.0, e),
+                y => y,
+            };
+        }
+        // -0 is shown as 0
+        self.get_float_str(bound + 0.0)
+    }
+
     fn get_float_str(&self, x: f64) -> String {
         let (scientific, scientific_fixed) = self.requires_scientific_notation(x);
+        if let Some(n) = self.sigfig {
+            return self.get_significant_str(x, n, scientific, scientific_fixed);
+        }
         let (rounding, fixed) =
             self.round
                 .unwrap_or(if scientific { (4, false) } else { (8, false) });
         if scientific {
-            get_scientific(x, rounding, scientific_fixed)
-        } else if rounding == 0 {
-            format!("{x:.0}")
+            return self.get_scientific_str(x, rounding, scientific_fixed);
+        }
+        let rounded = format!("{x:.rounding$}");
+        if fixed || rounding == 0 {
+            self.get_grouped_str(rounded)
         } else {
-            let rounded = format!("{x:.rounding$}");
-            if fixed {
-                rounded
-            } else {
+            self.get_grouped_str(
                 rounded
                     .trim_end_matches('0')
                     .trim_end_matches('.')
-                    .to_string()
+                    .to_string(),
+            )
+        }
+    }
+
+    // Big integers may not fit in a float, so the leading digits are scaled down by a power
+    // of 1000 to be formatted, then the exponent is shifted back
+    fn get_big_int_str(&self, n: &BigInt) -> String {
+        let digits = n.magnitude_digits();
+        let shift = (digits.len() - 1) / 3 * 3;
+        let leading = &digits[..digits.len().min(17)];
+        let scaled = leading.parse::<f64>().unwrap_or(0.0)
+            * 10f64.powi(digits.len() as i32 - shift as i32 - leading.len() as i32);
+        let scaled = if n.is_negative() { -scaled } else { scaled };
+
+        let (_, fixed) = self.requires_scientific_notation(n.to_f64());
+        let scientific_str = match self.sigfig {
+            Some(sigfig) => self.get_significant_str(scaled, sigfig, true, fixed),
+            None => {
+                let (rounding, _) = self.round.unwrap_or((4, false));
+                self.get_scientific_str(scaled, rounding, fixed)
             }
+        };
+        shift_exponent(&scientific_str, shift, fixed)
+    }
+
+    // Polar form gives the angle in radians, or in degrees if requested
+    fn get_complex_str(&self, re: f64, im: f64) -> String {
+        if let Some(degrees) = self.polar {
+            let z = Complex::new(re, im);
+            return if degrees {
+                format!(
+                    "{}∠{}°",
+                    self.get_float_str(z.abs()),
+                    self.get_float_str(z.arg().to_degrees())
+                )
+            } else {
+                format!(
+                    "{}∠{}",
+                    self.get_float_str(z.abs()),
+                    self.get_float_str(z.arg())
+                )
+            };
+        }
+
+        let im_str = match self.get_float_str(im.abs()) {
+            s if s == "1" => String::new(),
+            s => s,
+        };
+        let sign = if im < 0.0 { '-' } else { '+' };
+        if re == 0.0 {
+            format!("{}{im_str}i", if im < 0.0 { "-" } else { "" })
+        } else {
+            format!("{} {sign} {im_str}i", self.get_float_str(re))
+        }
+    }
+
+    // The uncertainty is rounded to 2 significant figures if its leading digit is 1, otherwise
+    // to 1, or to the 'sigfig' count, and the value is rounded to the same decimal place. Both share one exponent when
+    // scientific notation is used
+    fn get_uncertain_str(&self, x: f64, u: f64) -> String {
+        if u == 0.0 || !u.is_finite() {
+            return format!("({} ± {u})", self.get_float_str(x));
+        }
+
+        let (scientific, _) = self.requires_scientific_notation(x);
+        let exponent = match x {
+            0.0 => 0,
+            _ if scientific => x.abs().log10().floor() as i32,
+            _ => 0,
+        };
+        let (x, u) = (x / 10f64.powi(exponent), u / 10f64.powi(exponent));
+
+        let one_figure = format!("{u:.0e}");
+        let two_figures = format!("{u:.1e}");
+        let (rounded, extra_digit) = if let Some(n) = self.sigfig {
+            (format!("{u:.*e}", n - 1), n as i32 - 1)
+        } else if one_figure.starts_with('1') {
+            (one_figure, 1)
+        } else if two_figures.starts_with('1') {
+            (two_figures, 1)
+        } else {
+            (one_figure, 0)
+        };
+        let u_exponent = rounded
+            .split_once('e')
+            .map_or(0, |(_, e)| e.parse::<i32>().unwrap_or(0));
+        let decimals = extra_digit - u_exponent;
+        let (x_str, u_str) = if decimals >= 0 {
+            let decimals = decimals as usize;
+            (format!("{x:.decimals$}"), format!("{u:.decimals$}"))
+        } else {
+            let place = 10f64.powi(-decimals);
+            (
+                format!("{:.0}", (x / place).round() * place),
+                format!("{:.0}", (u / place).round() * place),
+            )
+        };
+
+        match exponent {
+            0 => format!(
+                "({} ± {})",
+                self.get_grouped_str(x_str),
+                self.get_grouped_str(u_str)
+            ),
+            _ => format!("({x_str} ± {u_str})e{exponent}"),
+        }
+    }
+
+    // Rectangular complex numbers are bracketed so that the units apply to both parts
+    fn get_number_with_units_str(
+        &self,
+        num: &AutoNum,
+        number_str: String,
+        unit_str: &str,
+    ) -> String {
+        if self.nounits {
+            number_str
+        } else if num.is_complex() && self.polar.is_none() {
+            format!("({number_str}) {unit_str}")
+        } else {
+            format!("{number_str} {unit_str}")
         }
     }
 
     fn get_autonum_str(&self, num: &AutoNum) -> String {
         match num {
+            AutoNum::Int(n) if self.digits.is_some() => self.get_grouped_str(n.to_string()),
+            AutoNum::Int(n) if self.sigfig.is_some() => self.get_float_str(*n as f64),
             AutoNum::Int(n) => {
                 let casted_value = *n as f64;
                 let (scientific, scientific_fixed) =
                     self.requires_scientific_notation(casted_value);
                 if scientific {
                     let (rounding, _) = self.round.unwrap_or((4, false));
-                    get_scientific(casted_value, rounding, scientific_fixed)
+                    self.get_scientific_str(casted_value, rounding, scientific_fixed)
                 } else {
-                    n.to_string()
+                    self.get_grouped_str(n.to_string())
                 }
             }
+            &AutoNum::Rational(n, d) => match self.fraction {
+                Some(FractionDisplay::Mixed) if n.abs() > d => {
+                    format!("{} {}/{d}", n / d, (n % d).abs())
+                }
+                Some(_) => format!("{n}/{d}"),
+                None => self.get_float_str(num.cast()),
+            },
             AutoNum::Float(x) => self.get_float_str(*x),
+            AutoNum::Big(n)
+                if self.digits.is_some()
+                    || matches!(self.scientific, Some(ScientificDisplay::Never)) =>
+            {
+                self.get_grouped_str(n.to_string())
+            }
+            AutoNum::Big(n) => self.get_big_int_str(n),
+            &AutoNum::Complex(re, im) => self.get_complex_str(re, im),
+            // commas are decimal points with European grouping
+            &AutoNum::Interval(lo, hi) => {
+                let separator = match self.get_group() {
+                    GroupDisplay::European => ';',
+                    _ => ',',
+                };
+                format!(
+                    "[{}{separator} {}]",
+                    self.get_bound_str(lo, true),
+                    self.get_bound_str(hi, false)
+                )
+            }
         }
     }
 
-    pub fn get_answer(&self) -> Result<String, EvalError> {
-        let answer = self.expr.eval()?;
-        if let Some((unit_str, unit_val)) = &self.unit {
-            if answer.units != unit_val.units {
-                return Err(EvalError {
-                    error: format!(
-                        "cannot convert to given units: {} -> {}",
-                        answer.units.to_si_units_str(),
-                        unit_str
-                    ),
-                });
+    fn get_base_str(&self, num: &AutoNum, base: &BaseDisplay) -> Result<String, EvalError> {
+        // Answers with units are usually floats, which are accepted when they are whole
+        let n = match *num {
+            AutoNum::Float(x) if x.fract() == 0.0 && x.abs() < i128::MAX as f64 => {
+                Some(BigInt::from_i128(x as i128))
             }
+            _ => num.as_big_int(),
+        };
+        let Some(n) = n else {
+            return Err(EvalError {
+                error: format!("'{}' query requires an integer answer", base.keyword),
+            });
+        };
 
-            let converted = answer.value.cast() / unit_val.value.cast();
-            let converted_str = self.get_float_str(converted);
-            if self.nounits {
-                Ok(format!("{converted_str}"))
-            } else {
-                Ok(format!("{converted_str} {unit_str}"))
+        let (n, digits) = match base.width {
+            Some(width) => {
+                let limit = BigInt::from_i128(2).pow(width);
+                let half = BigInt::from_i128(2).pow(width - 1);
+                if !n.sub(&limit).is_negative() || n.add(&half).is_negative() {
+                    return Err(EvalError {
+                        error: format!("{n} does not fit in {width} bits"),
+                    });
+                }
+                let n = if n.is_negative() { n.add(&limit) } else { n };
+                let width_digits = get_radix_digits(&limit.sub(&BigInt::from_i128(1)), base.radix);
+                let digits = get_radix_digits(&n, base.radix);
+                (n, format!("{digits:0>width$}", width = width_digits.len()))
+            }
+            None => {
+                let digits = get_radix_digits(&n, base.radix);
+                (n, digits)
             }
+        };
+
+        let digits = if self.get_group() != GroupDisplay::Off {
+            group_digits(&digits, 4, '_')
         } else {
-            let number_str = self.get_autonum_str(&answer.value);
-            if answer.units.no_units() || self.nounits {
-                Ok(number_str)
-            } else {
-                Ok(format!("{number_str} {}", answer.units.to_si_units_str()))
+            digits
+        };
+        let prefix = match base.radix {
+            2 => "0b",
+            8 => "0o",
+            16 => "0x",
+            _ => "",
+        };
+        let sign = if n.is_negative() { "-" } else { "" };
+        Ok(format!("{sign}{prefix}{digits}"))
+    }
+
+    // Named derived units are only combined with base units when that uses fewer terms,
+    // so that J K^-1 is preferred over kg m^2 s^-2 K^-1 but m s^-2 is not written as N kg^-1.
+    // Also returns whether a metric prefix can be added to the units
+    fn get_units_str(&self, units: &Dimension) -> (String, bool) {
+        let si_units_str = units.to_si_units_str();
+        let si_prefixable = units.base_unit_count() == 1
+            && !si_units_str.contains(['^', ' '])
+            && si_units_str != "kg";
+        if self.si_units {
+            return (si_units_str, si_prefixable);
+        }
+        if let Some((name, _)) = self.derived_units.iter().find(|(_, d)| d == units) {
+            return (String::from(*name), true);
+        }
+
+        let base_unit_count = units.base_unit_count();
+        if base_unit_count < 3 {
+            return (si_units_str, si_prefixable);
+        }
+
+        let mut best: Option<(&str, Dimension)> = None;
+        for (name, derived) in &self.derived_units {
+            let Ok(remainder) = units.combine(derived, true) else {
+                continue;
+            };
+            let count = remainder.base_unit_count() + 1;
+            let best_count = best
+                .as_ref()
+                .map_or(base_unit_count, |(_, d)| d.base_unit_count() + 1);
+            if count < best_count {
+                best = Some((name, remainder));
+            }
+        }
+
+        match best {
+            Some((name, remainder)) => (format!("{name} {}", remainder.to_si_units_str()), false),
+            None => (si_units_str, false),
+        }
+    }
+
+    // Gives the factor to divide the value by along with the prefixed units
+    fn get_prefixed(&self, x: f64, unit_str: &str, prefixable: bool) -> Option<(f64, String)> {
+        if !self.autoprefix || !prefixable {
+            return None;
+        }
+        // the prefix is chosen for the value as displayed, so 999.999 m round 2 is 1 km
+        let (prefix, factor) = match get_engineering_prefix(x) {
+            Some((prefix, factor)) => (Some(prefix), factor),
+            None => (None, 1.0),
+        };
+        let scaled = x / factor;
+        let rounded = match self.get_last_digit_exponent(scaled) {
+            Some(e) if e < 0 => (scaled * 10f64.powi(-e)).round() / 10f64.powi(-e),
+            Some(e) => (scaled / 10f64.powi(e)).round() * 10f64.powi(e),
+            None => scaled,
+        };
+        if rounded.abs() >= 1000.0 {
+            let (prefix, factor) = get_engineering_prefix(rounded * factor)?;
+            return Some((factor, format!("{prefix}{unit_str}")));
+        }
+        Some((factor, format!("{}{unit_str}", prefix?)))
+    }
+
+    pub fn evaluate(&self, env: &mut Environment) -> Result<Option<Quantity>, EvalError> {
+        env.set_complex(self.complex);
+        for statement in &self.statements {
+            match statement {
+                Statement::Assign(name, value) => {
+                    let value = value.eval(env)?;
+                    env.set_variable(name, value);
+                }
+                Statement::Define(name, function) => env.set_function(name, function.clone()),
+            }
+        }
+
+        self.expr.as_ref().map(|expr| expr.eval(env)).transpose()
+    }
+
+    pub fn get_answer(&self, env: &mut Environment) -> Result<Option<String>, EvalError> {
+        match self.evaluate(env)? {
+            Some(answer) => self.format_answer(&answer).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Conversions to the target units are kept exact so that the answer stays an integer
+    fn format_base_answer(
+        &self,
+        answer: &Quantity,
+        base: &BaseDisplay,
+    ) -> Result<String, EvalError> {
+        if answer.uncertainty.is_some() {
+            return Err(EvalError {
+                error: String::from("an answer with an uncertainty cannot be shown in a base"),
+            });
+        }
+        if let Some(target) = &self.unit {
+            let value = get_target_value(answer, target)?.auto_div(&target.value.value)?;
+            let base_str = self.get_base_str(&value, base)?;
+            Ok(self.get_number_with_units_str(&value, base_str, &target.name))
+        } else if answer.units.no_units() {
+            self.get_base_str(&answer.value, base)
+        } else {
+            let (unit_str, _) = self.get_units_str(&answer.units);
+            let base_str = self.get_base_str(&answer.value, base)?;
+            Ok(self.get_number_with_units_str(&answer.value, base_str, &unit_str))
+        }
+    }
+
+    // Durations as h:mm:ss, with a count of days in front once there is at least one
+    fn format_time_answer(&self, answer: &Quantity) -> Result<String, EvalError> {
+        if self.unit.is_some() {
+            return Err(EvalError {
+                error: String::from("'time' query cannot be used with 'in'"),
+            });
+        }
+        if self.base.is_some() {
+            return Err(EvalError {
+                error: String::from("'time' query cannot be used with a base"),
+            });
+        }
+        if self.sigfig.is_some() {
+            return Err(EvalError {
+                error: String::from("'time' query cannot be used with 'sigfig', use 'round'"),
+            });
+        }
+        if answer.units != Dimension::new(0, 0, 1, 0, 0, 0, 0, 1) {
+            return Err(EvalError {
+                error: format!(
+                    "'time' query requires a duration, not {}",
+                    answer.units.to_si_units_str()
+                ),
+            });
+        }
+        if answer.uncertainty.is_some() || answer.value.is_complex() || answer.value.is_interval() {
+            return Err(EvalError {
+                error: String::from("'time' query requires a real answer without uncertainty"),
+            });
+        }
+
+        let (rounding, fixed) = self.round.unwrap_or((8, false));
+        if rounding > 9 {
+            return Err(EvalError {
+                error: String::from("'time' query can show at most 9 decimal places"),
+            });
+        }
+        let x = answer.value.cast();
+        if x.is_nan() || x.abs() >= 2f64.powi(53) {
+            return Err(EvalError {
+                error: String::from("duration is too long to show exactly with 'time'"),
+            });
+        }
+
+        // Count in steps of the last displayed decimal so that the fields never round up to 60.
+        // The whole seconds are split off first, as scaling them could lose precision
+        let steps_per_second = 10i128.pow(rounding as u32);
+        let whole = x.abs().trunc();
+        let steps = whole as i128 * steps_per_second
+            + ((x.abs() - whole) * steps_per_second as f64).round() as i128;
+
+        let seconds = steps / steps_per_second % 60;
+        let minutes = steps / steps_per_second / 60 % 60;
+        let hours = steps / steps_per_second / 3600 % 24;
+        let days = steps / steps_per_second / 86400;
+
+        let mut fraction_str = match rounding {
+            0 => String::new(),
+            _ => format!(".{:0rounding$}", steps % steps_per_second),
+        };
+        if !fixed {
+            fraction_str = fraction_str
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string();
+        }
+
+        let sign = if x < 0.0 && steps != 0 { "-" } else { "" };
+        Ok(match days {
+            0 => format!("{sign}{hours}:{minutes:02}:{seconds:02}{fraction_str}"),
+            _ => format!("{sign}{days}d {hours:02}:{minutes:02}:{seconds:02}{fraction_str}"),
+        })
+    }
+
+    // Integer amounts of every unit but the last, e.g. '5 ft 3.5 inch'
+    fn format_mixed_answer(
+        &self,
+        answer: &Quantity,
+        first: &TargetUnits,
+    ) -> Result<String, EvalError> {
+        if self.sigfig.is_some() {
+            return Err(EvalError {
+                error: String::from(
+                    "a list of units cannot be combined with 'sigfig', use 'round'",
+                ),
+            });
+        }
+        if answer.uncertainty.is_some() || answer.value.is_complex() || answer.value.is_interval() {
+            return Err(EvalError {
+                error: String::from("a list of units requires a real answer without uncertainty"),
+            });
+        }
+        let targets: Vec<&TargetUnits> = std::iter::once(first).chain(&self.mixed_units).collect();
+        for target in &targets {
+            get_target_value(answer, target)?;
+        }
+
+        // Work in multiples of the last unit, rounded as it will be displayed
+        let last = targets[targets.len() - 1];
+        let last_scale = last.value.value.cast();
+        let x = answer.value.cast() / last_scale;
+        if x.is_nan() || x.abs() >= 2f64.powi(53) {
+            return Err(EvalError {
+                error: String::from("answer is too large to split exactly into a list of units"),
+            });
+        }
+        let rounding = self.round.map_or(8, |(n, _)| n) as i32;
+        let mut remaining = (x.abs() * 10f64.powi(rounding)).round() / 10f64.powi(rounding);
+
+        let mut parts = Vec::new();
+        for target in &targets[..targets.len() - 1] {
+            let ratio = target.value.value.cast() / last_scale;
+            let count = (remaining / ratio + 1e-9).floor();
+            remaining = (remaining - count * ratio).max(0.0);
+            if count != 0.0 || !parts.is_empty() {
+                parts.push(self.get_number_with_units_str(
+                    &answer.value,
+                    self.get_grouped_str((count as i64).to_string()),
+                    &target.name,
+                ));
+            }
+        }
+        parts.push(self.get_number_with_units_str(
+            &answer.value,
+            self.get_float_str(remaining),
+            &last.name,
+        ));
+
+        let sign = if x < 0.0 { "-" } else { "" };
+        Ok(format!("{sign}{}", parts.join(" ")))
+    }
+
+    pub fn format_answer(&self, answer: &Quantity) -> Result<String, EvalError> {
+        if self.digits.is_some() && !matches!(answer.value, AutoNum::Int(_) | AutoNum::Big(_)) {
+            return Err(EvalError {
+                error: String::from("'digits' query requires an integer answer"),
+            });
+        }
+        if let Some(DigitsDisplay::Count) = self.digits {
+            return match &answer.value {
+                AutoNum::Big(n) => Ok(n.digit_count().to_string()),
+                AutoNum::Int(n) => Ok(n.unsigned_abs().to_string().len().to_string()),
+                _ => unreachable!(),
+            };
+        }
+
+        if self.time {
+            return self.format_time_answer(answer);
+        }
+
+        if let Some(base) = &self.base {
+            return self.format_base_answer(answer, base);
+        }
+
+        if let Some(target) = &self.unit {
+            if !self.mixed_units.is_empty() {
+                return self.format_mixed_answer(answer, target);
+            }
+            let value = get_target_value(answer, target)?;
+            if value.is_complex() || value.is_interval() {
+                let converted = value.auto_div(&target.value.value)?;
+                let converted_str = self.get_autonum_str(&converted);
+                return Ok(self.get_number_with_units_str(&converted, converted_str, &target.name));
             }
+
+            let scale = target.value.value.cast();
+            let converted = value.cast() / scale;
+            let (factor, unit_str) = self
+                .get_prefixed(converted, &target.name, target.prefixable)
+                .unwrap_or((1.0, target.name.clone()));
+            let converted_str = match answer.uncertainty {
+                Some(u) => self.get_uncertain_str(converted / factor, u / scale.abs() / factor),
+                None => self.get_float_str(converted / factor),
+            };
+            Ok(self.get_number_with_units_str(&value, converted_str, &unit_str))
+        } else if answer.units.no_units() {
+            match answer.uncertainty {
+                Some(u) => Ok(self.get_uncertain_str(answer.value.cast(), u)),
+                None => Ok(self.get_autonum_str(&answer.value)),
+            }
+        } else {
+            let (unit_str, prefixable) = self.get_units_str(&answer.units);
+            let x = answer.value.cast();
+            let prefixed = self.get_prefixed(x, &unit_str, prefixable);
+            let factor = prefixed.as_ref().map_or(1.0, |(factor, _)| *factor);
+            let number_str = match answer.uncertainty {
+                Some(u) => self.get_uncertain_str(x / factor, u / factor),
+                None if prefixed.is_some() => self.get_float_str(x / factor),
+                None => self.get_autonum_str(&answer.value),
+            };
+            let unit_str = prefixed.map_or(unit_str, |(_, prefixed_unit_str)| prefixed_unit_str);
+            Ok(self.get_number_with_units_str(&answer.value, number_str, &unit_str))
         }
     }
 }
 
+// The answer's value relative to the zero of the target units, before scaling
+fn get_target_value(answer: &Quantity, target: &TargetUnits) -> Result<AutoNum, EvalError> {
+    if answer.units != target.value.units {
+        return Err(EvalError {
+            error: format!(
+                "cannot convert to given units: {} -> {}",
+                answer.units.to_si_units_str(),
+                target.name
+            ),
+        });
+    }
+
+    Ok(match &target.offset {
+        Some(offset) if answer.temperature != TemperatureKind::Difference => {
+            answer.value.auto_sub(offset)
+        }
+        _ => answer.value.clone(),
+    })
+}
+
 fn parse_in_query<'a>(
     lexer: &mut Lexer<'a>,
     defs: &Definitions<'a>,
@@ -173,13 +985,76 @@ fn parse_in_query<'a>(
     if query.unit.is_some() {
         return Err(ParseError::from_token(
             String::from("'in' query already specified"),
-            &query_token,
-            &lexer.original,
+            query_token,
+            lexer.original,
         ));
     }
 
-    let units = parse_units(lexer, defs)?;
-    query.unit = Some(units);
+    if let Some((name, scale, offset)) = parse_affine_unit(lexer, defs)? {
+        query.unit = Some(TargetUnits {
+            name: String::from(name),
+            value: scale,
+            offset: Some(offset),
+            prefixable: false,
+        });
+    } else {
+        let (name, value) = parse_units(lexer, defs)?;
+        let prefixable = defs.is_prefixable_unit(&name);
+        query.unit = Some(TargetUnits {
+            name,
+            value,
+            offset: None,
+            prefixable,
+        });
+    }
+
+    // A comma separated list of units, e.g. 'in ft, inch'
+    while let TokenData::Sym(',') = lexer.peek_token()?.data {
+        let comma_token = lexer.next_token()?;
+        if query
+            .unit
+            .as_ref()
+            .is_some_and(|unit| unit.offset.is_some())
+        {
+            return Err(ParseError::from_token(
+                String::from("a list of units cannot contain affine units"),
+                &comma_token,
+                lexer.original,
+            ));
+        }
+        let units_token = lexer.peek_token()?;
+        if parse_affine_unit(lexer, defs)?.is_some() {
+            return Err(ParseError::from_token(
+                String::from("a list of units cannot contain affine units"),
+                &units_token,
+                lexer.original,
+            ));
+        }
+        let (name, value) = parse_units(lexer, defs)?;
+        if name.is_empty() {
+            return Err(ParseError::from_token(
+                String::from("expected units after ','"),
+                &units_token,
+                lexer.original,
+            ));
+        }
+        let previous = query.mixed_units.last().or(query.unit.as_ref());
+        if previous.is_some_and(|previous| {
+            previous.value.units == value.units && previous.value.value.cast() <= value.value.cast()
+        }) {
+            return Err(ParseError::from_token(
+                String::from("a list of units must go from the largest unit to the smallest"),
+                &units_token,
+                lexer.original,
+            ));
+        }
+        query.mixed_units.push(TargetUnits {
+            name,
+            value,
+            offset: None,
+            prefixable: false,
+        });
+    }
     Ok(())
 }
 
@@ -189,11 +1064,11 @@ fn parse_round_query<'a>(
     query_token: &Token<'a>,
     fixed: bool,
 ) -> Result<(), ParseError<'a>> {
-    if query.round.is_some() {
+    if query.round.is_some() || query.sigfig.is_some() {
         return Err(ParseError::from_token(
-            String::from("'round' query already specified"),
-            &query_token,
-            &lexer.original,
+            String::from("'round'/'sigfig' query already specified"),
+            query_token,
+            lexer.original,
         ));
     }
 
@@ -210,6 +1085,154 @@ fn parse_round_query<'a>(
     }
 }
 
+fn parse_sigfig_query<'a>(
+    lexer: &mut Lexer<'a>,
+    query: &mut Query,
+    query_token: &Token<'a>,
+) -> Result<(), ParseError<'a>> {
+    if query.round.is_some() || query.sigfig.is_some() {
+        return Err(ParseError::from_token(
+            String::from("'round'/'sigfig' query already specified"),
+            query_token,
+            lexer.original,
+        ));
+    }
+
+    let sigfig_token = lexer.next_token()?;
+    match sigfig_token.data {
+        TokenData::Int(n) if n > 0 => {
+            query.sigfig = Some(n as usize);
+            Ok(())
+        }
+        _ => Err(ParseError::from_token(
+            String::from("expected positive integer for sigfig query"),
+            &sigfig_token,
+            lexer.original,
+        )),
+    }
+}
+
+fn parse_digits_query<'a>(
+    lexer: &mut Lexer<'a>,
+    query: &mut Query,
+    query_token: &Token<'a>,
+) -> Result<(), ParseError<'a>> {
+    if query.digits.is_some() {
+        return Err(ParseError::from_token(
+            String::from("'digits' query already specified"),
+            query_token,
+            lexer.original,
+        ));
+    }
+
+    let subquery_token = lexer.next_token()?;
+    match subquery_token.data {
+        TokenData::Word("all") => {
+            query.digits = Some(DigitsDisplay::All);
+            Ok(())
+        }
+        TokenData::Word("count") => {
+            query.digits = Some(DigitsDisplay::Count);
+            Ok(())
+        }
+        _ => Err(ParseError::from_token(
+            String::from("expected 'all'/'count' for 'digits' query"),
+            &subquery_token,
+            lexer.original,
+        )),
+    }
+}
+
+fn parse_base_query<'a>(
+    lexer: &mut Lexer<'a>,
+    query: &mut Query,
+    query_word: &'static str,
+    query_token: &Token<'a>,
+) -> Result<(), ParseError<'a>> {
+    if query.base.is_some() {
+        return Err(ParseError::from_token(
+            String::from("'hex'/'bin'/'oct'/'base' query already specified"),
+            query_token,
+            lexer.original,
+        ));
+    }
+
+    let radix = match query_word {
+        "hex" => 16,
+        "bin" => 2,
+        "oct" => 8,
+        _ => {
+            let radix_token = lexer.next_token()?;
+            match radix_token.data {
+                TokenData::Int(n @ 2..=36) => n as u32,
+                _ => {
+                    return Err(ParseError::from_token(
+                        String::from("expected integer from 2 to 36 for 'base' query"),
+                        &radix_token,
+                        lexer.original,
+                    ));
+                }
+            }
+        }
+    };
+
+    let width_token = lexer.peek_token()?;
+    let width = match width_token.data {
+        TokenData::Int(n @ 1..=1024) => Some(n as u32),
+        TokenData::Int(_) | TokenData::Big(_) => {
+            return Err(ParseError::from_token(
+                String::from("expected bit width from 1 to 1024"),
+                &width_token,
+                lexer.original,
+            ));
+        }
+        _ => None,
+    };
+    if width.is_some() {
+        let _ = lexer.next_token();
+    }
+
+    query.base = Some(BaseDisplay {
+        keyword: query_word,
+        radix,
+        width,
+    });
+    Ok(())
+}
+
+// Without a style, the configured grouping is used, or commas if there is none
+fn parse_group_query<'a>(
+    lexer: &mut Lexer<'a>,
+    query: &mut Query,
+    query_token: &Token<'a>,
+) -> Result<(), ParseError<'a>> {
+    if query.group.is_some() {
+        return Err(ParseError::from_token(
+            String::from("'group' query already specified"),
+            query_token,
+            lexer.original,
+        ));
+    }
+
+    let style = match lexer.peek_token()?.data {
+        TokenData::Word(w) => GroupDisplay::from_name(w),
+        _ => None,
+    };
+    query.group = match style {
+        Some(style) => {
+            let _ = lexer.next_token();
+            Some(style)
+        }
+        None => Some(
+            query
+                .default_group
+                .filter(|&group| group != GroupDisplay::Off)
+                .unwrap_or(GroupDisplay::Comma),
+        ),
+    };
+    Ok(())
+}
+
 fn parse_scientific_if_query<'a>(
     lexer: &mut Lexer<'a>,
     query: &mut Query,
@@ -250,8 +1273,8 @@ fn parse_scientific_if_query<'a>(
 
     if limit_lo.is_none() && limit_hi.is_none() {
         return Err(ParseError::from_token(
-            format!("expected 'more'/'less' for 'scientific if' subquery"),
-            &query_token,
+            String::from("expected 'more'/'less' for 'scientific if' subquery"),
+            query_token,
             lexer.original,
         ));
     }
@@ -271,7 +1294,7 @@ fn parse_scientific_query<'a>(
     if query.scientific.is_some() {
         return Err(ParseError::from_token(
             String::from("'scientific' query already specified"),
-            &query_token,
+            query_token,
             lexer.original,
         ));
     }
@@ -293,9 +1316,18 @@ fn parse_scientific_query<'a>(
                 Ok(())
             }
         }
+        TokenData::Word("engineering" | "eng") => {
+            let token = lexer.peek_token()?;
+            let fixed = matches!(token.data, TokenData::Word("fixed" | "fd"));
+            if fixed {
+                let _ = lexer.next_token();
+            }
+            query.scientific = Some(ScientificDisplay::Engineering(fixed));
+            Ok(())
+        }
         TokenData::Word("if") => parse_scientific_if_query(lexer, query, &subquery_token),
         _ => Err(ParseError::from_token(
-            String::from("expected 'never'/'always'/'if' for 'scientific' query"),
+            String::from("expected 'never'/'always'/'engineering'/'if' for 'scientific' query"),
             &subquery_token,
             lexer.original,
         )),
@@ -313,7 +1345,92 @@ fn parse_query<'a>(
         "in" => parse_in_query(lexer, defs, query, query_token),
         "round" | "rd" => parse_round_query(lexer, query, query_token, false),
         "fixed" | "fd" => parse_round_query(lexer, query, query_token, true),
+        "sigfig" | "sf" => parse_sigfig_query(lexer, query, query_token),
         "scientific" | "sc" => parse_scientific_query(lexer, query, query_token),
+        "digits" => parse_digits_query(lexer, query, query_token),
+        "hex" => parse_base_query(lexer, query, "hex", query_token),
+        "bin" => parse_base_query(lexer, query, "bin", query_token),
+        "oct" => parse_base_query(lexer, query, "oct", query_token),
+        "base" => parse_base_query(lexer, query, "base", query_token),
+        "group" => parse_group_query(lexer, query, query_token),
+        "time" => {
+            if query.time {
+                Err(ParseError::from_token(
+                    String::from("'time' query already specified"),
+                    query_token,
+                    lexer.original,
+                ))
+            } else {
+                query.time = true;
+                Ok(())
+            }
+        }
+        "autoprefix" | "ap" => {
+            if query.autoprefix {
+                Err(ParseError::from_token(
+                    String::from("'autoprefix' query already specified"),
+                    query_token,
+                    lexer.original,
+                ))
+            } else {
+                query.autoprefix = true;
+                Ok(())
+            }
+        }
+        "fraction" | "frac" | "mixed" => {
+            if query.fraction.is_some() {
+                Err(ParseError::from_token(
+                    String::from("'fraction'/'mixed' query already specified"),
+                    query_token,
+                    lexer.original,
+                ))
+            } else if query_word == "mixed" {
+                query.fraction = Some(FractionDisplay::Mixed);
+                Ok(())
+            } else {
+                query.fraction = Some(FractionDisplay::Improper);
+                Ok(())
+            }
+        }
+        "complex" => {
+            if query.complex {
+                Err(ParseError::from_token(
+                    String::from("'complex' query already specified"),
+                    query_token,
+                    lexer.original,
+                ))
+            } else {
+                query.complex = true;
+                Ok(())
+            }
+        }
+        "polar" => {
+            if query.polar.is_some() {
+                return Err(ParseError::from_token(
+                    String::from("'polar' query already specified"),
+                    query_token,
+                    lexer.original,
+                ));
+            }
+            let degrees = matches!(lexer.peek_token()?.data, TokenData::Word("deg"));
+            if degrees {
+                let _ = lexer.next_token();
+            }
+            query.polar = Some(degrees);
+            Ok(())
+        }
+        "si" => {
+            if query.si_units {
+                Err(ParseError::from_token(
+                    String::from("'si' query already specified"),
+                    query_token,
+                    lexer.original,
+                ))
+            } else {
+                query.si_units = true;
+                Ok(())
+            }
+        }
         "hideunits" | "hide" => {
             if query.nounits {
                 Err(ParseError::from_token(
@@ -328,22 +1445,68 @@ fn parse_query<'a>(
         }
         _ => Err(ParseError::from_token(
             format!("unknown query keyword '{}'", query_word),
-            &query_token,
+            query_token,
             lexer.original,
         )),
     }
 }
 
-pub fn parse<'a>(s: &'a str, defs: &Definitions<'a>) -> Result<Query, ParseError<'a>> {
+fn parse_statements<'a>(
+    lexer: &mut Lexer<'a>,
+    ctx: &mut Context<'a, '_>,
+) -> Result<(Vec<Statement>, Option<Expr>), ParseError<'a>> {
+    let mut statements = Vec::new();
+    loop {
+        let final_expr = if let Some((name, function)) = parse_function_definition(lexer, ctx)? {
+            ctx.declare_function(name, function.params.len());
+            statements.push(Statement::Define(String::from(name), Rc::new(function)));
+            None
+        } else if let Some(name) = parse_assignment_target(lexer, ctx)? {
+            let value = parse_expr(lexer, ctx)?;
+            ctx.declare_variable(name);
+            statements.push(Statement::Assign(String::from(name), value));
+            Some(Expr::Variable(String::from(name)))
+        } else {
+            let expr = parse_expr(lexer, ctx)?;
+            return Ok((statements, Some(expr)));
+        };
+
+        if !matches!(lexer.peek_token()?.data, TokenData::Sym(';')) {
+            return Ok((statements, final_expr));
+        }
+        let _ = lexer.next_token();
+    }
+}
+
+pub fn parse<'a>(
+    s: &'a str,
+    defs: &Definitions<'a>,
+    env: &Environment,
+) -> Result<Query, ParseError<'a>> {
     let mut lexer = Lexer::new(s);
-    let expr = parse_expr(&mut lexer, defs)?;
+    let mut ctx = Context::new(defs, env);
+    let (statements, expr) = parse_statements(&mut lexer, &mut ctx)?;
 
     let mut query = Query {
+        statements,
         expr,
         unit: None,
+        mixed_units: Vec::new(),
         round: None,
+        sigfig: None,
+        fraction: None,
+        digits: None,
+        complex: false,
+        polar: None,
+        base: None,
+        group: None,
+        time: false,
+        default_group: env.default_group(),
         scientific: None,
         nounits: false,
+        si_units: false,
+        autoprefix: false,
+        derived_units: defs.get_derived_units(),
     };
 
     loop {
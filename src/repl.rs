@@ -0,0 +1,135 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use crate::{config, consts::Definitions, env::Environment, query};
+
+const PROMPT: &str = "> ";
+const HISTORY_LIMIT: usize = 1000;
+
+struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    fn load() -> History {
+        let path = config::history_path();
+        let entries = path
+            .as_ref()
+            .and_then(|p| File::open(p).ok())
+            .map(|f| BufReader::new(f).lines().map_while(Result::ok).collect())
+            .unwrap_or_default();
+        let mut history = History { path, entries };
+        history.truncate();
+        history
+    }
+
+    // Drops the oldest entries past the limit, returning whether any were dropped
+    fn truncate(&mut self) -> bool {
+        let excess = self.entries.len().saturating_sub(HISTORY_LIMIT);
+        self.entries.drain(..excess);
+        excess > 0
+    }
+
+    fn push(&mut self, line: &str) {
+        self.entries.push(String::from(line));
+        let truncated = self.truncate();
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // Once the limit is reached the file is rewritten rather than appended to
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| match truncated {
+                true => File::create(path),
+                false => OpenOptions::new().create(true).append(true).open(path),
+            })
+            .and_then(|mut f| match truncated {
+                true => self
+                    .entries
+                    .iter()
+                    .try_for_each(|entry| writeln!(f, "{entry}")),
+                false => writeln!(f, "{line}"),
+            });
+        if let Err(e) = written {
+            eprintln!("Unable to save history to {}: {e}", path.display());
+            self.path = None;
+        }
+    }
+
+    fn display(&self) {
+        for (i, entry) in self.entries.iter().enumerate() {
+            println!("{:>5}  {entry}", i + 1);
+        }
+    }
+}
+
+fn evaluate_line(line: &str, defs: &Definitions, env: &mut Environment) {
+    let query = match query::parse(line, defs, env) {
+        Ok(query) => query,
+        Err(e) => {
+            e.display_error_below_prompt(PROMPT.len());
+            return;
+        }
+    };
+
+    // 'ans' is only updated once the answer could also be displayed
+    let result = query.evaluate(env).and_then(|answer| match answer {
+        Some(answer) => {
+            let answer_str = query.format_answer(&answer)?;
+            env.set_variable("ans", answer);
+            Ok(Some(answer_str))
+        }
+        None => Ok(None),
+    });
+    match result {
+        Ok(Some(answer)) => println!("{answer}"),
+        Ok(None) => {}
+        Err(e) => eprintln!("Calculation error: {}", e.error),
+    }
+}
+
+pub fn run(defs: &Definitions) -> ExitCode {
+    let mut history = History::load();
+    let mut env = Environment::load();
+    let stdin = io::stdin();
+    let mut line = String::new();
+
+    loop {
+        print!("{PROMPT}");
+        let _ = io::stdout().flush();
+
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => {
+                println!();
+                return ExitCode::SUCCESS;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Unable to read input: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+
+        let input = line.trim_end_matches(['\n', '\r']);
+        match input.trim() {
+            "" => continue,
+            "exit" | "quit" => return ExitCode::SUCCESS,
+            "history" => {
+                history.display();
+                continue;
+            }
+            _ => {}
+        }
+
+        history.push(input);
+        evaluate_line(input, defs, &mut env);
+    }
+}
@@ -17,6 +17,7 @@ pub struct Dimension {
 }
 
 impl Dimension {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         mass: i8,
         length: i8,
@@ -34,12 +35,14 @@ impl Dimension {
         .simplify()
     }
 
+    // Reduces the exponents and denominator to lowest terms, keeping the denominator positive
     pub fn simplify(&self) -> Dimension {
         let dividing_factor = self
             .exponents
             .iter()
-            .fold(self.denom, |acc, n| gcd(acc, n.abs()));
-        let mut result_exponents = self.exponents.clone();
+            .fold(self.denom.abs(), |acc, n| gcd(acc, n.abs()))
+            * self.denom.signum();
+        let mut result_exponents = self.exponents;
         for exponent in &mut result_exponents {
             *exponent /= dividing_factor;
         }
@@ -53,21 +56,21 @@ impl Dimension {
         let gcd_ab = gcd(self.denom, other.denom);
         let factor_a = other.denom / gcd_ab;
         let factor_b = self.denom / gcd_ab;
-        let mut result_exponents = self.exponents.clone();
-        let added_correctly = result_exponents
-            .iter_mut()
-            .zip(other.exponents)
-            .map(|(a, b)| -> bool {
-                a.checked_mul(factor_a)
-                    .zip(b.checked_mul(factor_b))
-                    .and_then(|(a_norm, b_norm)| match div_other {
-                        true => a_norm.checked_sub(b_norm),
-                        false => a_norm.checked_add(b_norm),
-                    })
-                    .inspect(|x| *a = *x)
-                    .is_some()
-            })
-            .all(|b| b);
+        let mut result_exponents = self.exponents;
+        let added_correctly =
+            result_exponents
+                .iter_mut()
+                .zip(other.exponents)
+                .all(|(a, b)| -> bool {
+                    a.checked_mul(factor_a)
+                        .zip(b.checked_mul(factor_b))
+                        .and_then(|(a_norm, b_norm)| match div_other {
+                            true => a_norm.checked_sub(b_norm),
+                            false => a_norm.checked_add(b_norm),
+                        })
+                        .inspect(|x| *a = *x)
+                        .is_some()
+                });
 
         if !added_correctly {
             return Err(EvalError {
@@ -97,7 +100,7 @@ impl Dimension {
     }
 
     pub fn pow(&self, n: i8) -> Result<Dimension, EvalError> {
-        let mut result_exponents = self.exponents.clone();
+        let mut result_exponents = self.exponents;
         for r in &mut result_exponents {
             match r.checked_mul(n) {
                 Some(exp) => *r = exp,
@@ -120,6 +123,10 @@ impl Dimension {
         self.exponents.iter().all(|n| *n == 0)
     }
 
+    pub fn base_unit_count(&self) -> usize {
+        self.exponents.iter().filter(|n| **n != 0).count()
+    }
+
     const SI_UNIT_NAMES: [&'static str; 7] = ["kg", "m", "s", "A", "K", "mol", "cd"];
     pub fn to_si_units_str(&self) -> String {
         if self.no_units() {
@@ -133,7 +140,7 @@ impl Dimension {
                 if e == 0 {
                     return None;
                 }
-                let frac_gcd = gcd(e, self.denom);
+                let frac_gcd = gcd(e.abs(), self.denom);
                 let simpl_numerator = e / frac_gcd;
                 let simpl_denom = self.denom / frac_gcd;
                 match (simpl_numerator, simpl_denom) {
@@ -147,21 +154,78 @@ impl Dimension {
     }
 }
 
+// Temperatures given in units with an offset (degC, degF) are points on a scale, and only
+// their differences can be added to or subtracted from them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TemperatureKind {
+    Plain,
+    Absolute,
+    Difference,
+}
+
 #[derive(Clone)]
 pub struct Quantity {
     pub value: AutoNum,
     pub units: Dimension,
+    pub temperature: TemperatureKind,
+    // Absolute standard uncertainty, in the same units as the value
+    pub uncertainty: Option<f64>,
 }
 
 impl Quantity {
     pub fn new(value: AutoNum, units: Dimension) -> Quantity {
-        Quantity { value, units }
+        Quantity {
+            value,
+            units,
+            temperature: TemperatureKind::Plain,
+            uncertainty: None,
+        }
     }
 
     pub fn dimensionless(value: AutoNum) -> Quantity {
+        Quantity::new(value, Dimension::new(0, 0, 0, 0, 0, 0, 0, 1))
+    }
+
+    pub fn with_temperature(self, temperature: TemperatureKind) -> Quantity {
         Quantity {
-            value,
-            units: Dimension::new(0, 0, 0, 0, 0, 0, 0, 1),
+            temperature,
+            ..self
+        }
+    }
+
+    pub fn with_uncertainty(self, uncertainty: Option<f64>) -> EvalResult {
+        if uncertainty.is_some() && (self.value.is_complex() || self.value.is_interval()) {
+            return Err(EvalError {
+                error: String::from("uncertainties are only supported for real numbers"),
+            });
+        }
+        Ok(Quantity {
+            uncertainty,
+            ..self
+        })
+    }
+
+    pub fn added_temperature_kind(
+        &self,
+        other: &Quantity,
+        subtract: bool,
+    ) -> Result<TemperatureKind, EvalError> {
+        use TemperatureKind::*;
+        match (self.temperature, other.temperature, subtract) {
+            (Absolute, Absolute, false) => Err(EvalError {
+                error: String::from(
+                    "cannot add two absolute temperatures, subtract them to get a temperature difference",
+                ),
+            }),
+            (Difference, Absolute, true) => Err(EvalError {
+                error: String::from(
+                    "cannot subtract an absolute temperature from a temperature difference",
+                ),
+            }),
+            (Absolute | Plain, Absolute, true) => Ok(Difference),
+            (Absolute, _, _) | (_, Absolute, false) => Ok(Absolute),
+            (Difference, _, _) | (_, Difference, _) => Ok(Difference),
+            (Plain, Plain, _) => Ok(Plain),
         }
     }
 
@@ -198,27 +262,32 @@ impl Quantity {
         }
         if self.units.no_units() {
             return Ok(Quantity::new(
-                self.value.auto_pow(&other.value),
+                self.value.auto_pow(&other.value)?,
                 self.units.clone(),
             ));
         }
 
-        if let AutoNum::Int(n) = other.value {
-            if n <= 127 && n >= -128 {
-                let units = self.units.pow(n as i8)?;
-                Ok(Quantity::new(self.value.auto_pow(&other.value), units))
-            } else {
-                Err(EvalError {
-                    error: String::from(
-                        "power too large to raise quantity with units (max: -128 <= n <= 127)",
-                    ),
-                })
+        let (n, d) = match other.value {
+            AutoNum::Int(n) => (n, 1),
+            AutoNum::Rational(n, d) => (n, d),
+            // always out of the range checked below
+            AutoNum::Big(_) => (i64::MAX, 1),
+            AutoNum::Float(_) | AutoNum::Complex(..) | AutoNum::Interval(..) => {
+                return Err(EvalError {
+                    error: String::from("raising a quantity with units to a non-integer power not supported (power cannot be guaranteed to be an integer)"),
+                });
             }
-        } else {
-            Err(EvalError {
-                error: String::from("raising a quantity with units to a non-integer power not supported (power cannot be guaranteed to be an integer)"),
-            })
+        };
+        if !(-128..=127).contains(&n) || d > 127 {
+            return Err(EvalError {
+                error: String::from(
+                    "power too large to raise quantity with units (max: -128 <= n <= 127)",
+                ),
+            });
         }
+
+        let units = self.units.pow(n as i8)?.root(d as i8)?;
+        Ok(Quantity::new(self.value.auto_pow(&other.value)?, units))
     }
 
     pub fn unitless_op(&self, op: fn(&AutoNum) -> AutoNumResult, error_msg: &str) -> EvalResult {